@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use super::mailcap::*;
+use super::mimetype::get_extensions;
+
+const CACHE_HEADER: &str = "run-mailcap-rs cache 1";
+
+// One line per source file, that changes whenever the file is modified,
+// replaced, created or removed
+fn source_stamps(paths: &[&Path]) -> Option<Vec<String>> {
+    let mut stamps = Vec::new();
+    for path in paths {
+        let path = path.to_str()?;
+        if path.contains('\n') {
+            return None;
+        }
+        let stamp = match fs::metadata(path) {
+            Ok(metadata) => {
+                let mtime = metadata.modified().ok()
+                    .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+                    .map(|mtime| format!("{}.{:09}", mtime.as_secs(), mtime.subsec_nanos()))
+                    .unwrap_or_else(|| String::from("-"));
+                format!("source {} {} {}", mtime, metadata.len(), path)
+            },
+            Err(_e) => format!("source - - {}", path),
+        };
+        stamps.push(stamp);
+    }
+    Some(stamps)
+}
+
+fn read_cache(path: &Path, stamps: &[String]) -> Option<Vec<String>> {
+    let file = File::open(path).ok()?;
+    let mut lines = BufReader::new(file).lines();
+
+    if lines.next()?.ok()? != CACHE_HEADER {
+        return None;
+    }
+    for stamp in stamps {
+        if lines.next()?.ok()? != *stamp {
+            return None;
+        }
+    }
+    if lines.next()?.ok()? != "data" {
+        return None;
+    }
+    lines.collect::<Result<Vec<String>, io::Error>>().ok()
+}
+
+fn write_cache(path: &Path, stamps: &[String], data: &[String]) -> Result<(), io::Error> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Write to a private file first, so concurrent runs never see a
+    // partially written cache
+    let mut temp_path = path.as_os_str().to_os_string();
+    temp_path.push(format!(".{}", process::id()));
+    let temp_path = PathBuf::from(temp_path);
+
+    let result = (|| {
+        let mut file = BufWriter::new(File::create(&temp_path)?);
+        writeln!(file, "{}", CACHE_HEADER)?;
+        for line in stamps.iter().map(String::as_str).chain(Some("data")).chain(data.iter().map(String::as_str)) {
+            writeln!(file, "{}", line)?;
+        }
+        file.flush()?;
+        fs::rename(&temp_path, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct MimeTypesIndex {
+    extensions: HashMap<String, String>,
+    file_opened: bool,
+}
+
+impl MimeTypesIndex {
+    fn build(mime_paths: &[&Path]) -> Result<MimeTypesIndex, io::Error> {
+        let mut index = MimeTypesIndex {
+            extensions: HashMap::new(),
+            file_opened: false,
+        };
+
+        for path in mime_paths {
+            let file = match File::open(path) {
+                Ok(file) => file,
+                Err(_e) => continue,
+            };
+            index.file_opened = true;
+
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if line.starts_with('#') {
+                    continue;
+                }
+                let mut items = line.split_whitespace();
+                if let Some(mime) = items.next() {
+                    if mime.matches('/').count() != 1 {
+                        continue;
+                    }
+                    for item in items {
+                        index.extensions.entry(item.to_string()).or_insert_with(|| mime.to_string());
+                    }
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    fn serialize(&self) -> Vec<String> {
+        let mut data = Vec::new();
+        if self.file_opened {
+            data.push(String::from("opened"));
+        }
+        for (extension, mime) in &self.extensions {
+            data.push(format!("{} {}", mime, extension));
+        }
+        data
+    }
+
+    fn deserialize(data: &[String]) -> Option<MimeTypesIndex> {
+        let mut index = MimeTypesIndex {
+            extensions: HashMap::new(),
+            file_opened: false,
+        };
+        for line in data {
+            if line == "opened" {
+                index.file_opened = true;
+                continue;
+            }
+            let (mime, extension) = line.split_once(' ')?;
+            index.extensions.insert(extension.to_string(), mime.to_string());
+        }
+        Some(index)
+    }
+
+    pub fn load(cache_dir: &Path, mime_paths: &[&Path]) -> Result<MimeTypesIndex, io::Error> {
+        let stamps = source_stamps(mime_paths);
+        let cache_path = cache_dir.join("mime.types");
+
+        if let Some(ref stamps) = stamps {
+            if let Some(index) = read_cache(&cache_path, stamps).and_then(|data| MimeTypesIndex::deserialize(&data)) {
+                return Ok(index);
+            }
+        }
+
+        let index = MimeTypesIndex::build(mime_paths)?;
+        if let Some(ref stamps) = stamps {
+            // The cache is only an optimization, so failing to write it is fine
+            let _ = write_cache(&cache_path, stamps, &index.serialize());
+        }
+        Ok(index)
+    }
+
+    // Same result as mimetype::get_type_by_extension with the files, the
+    // index was built from
+    pub fn get_type_by_extension(&self, filename: &str) -> Result<String, io::Error> {
+        let extensions = get_extensions(filename);
+        if extensions.is_empty() {
+            return Ok(String::from("application/octet-stream"));
+        }
+        if !self.file_opened {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "No usable mime.types file found"));
+        }
+        for extension in &extensions {
+            if let Some(mime) = self.extensions.get(extension) {
+                return Ok(mime.clone());
+            }
+        }
+        Ok(String::from("application/octet-stream"))
+    }
+}
+
+fn major_type(mime_type: &str) -> &str {
+    mime_type.split('/').next().unwrap()
+}
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub struct MailcapIndex {
+    entries: Vec<MailcapEntry>,
+    by_major_type: HashMap<String, Vec<usize>>,
+}
+
+impl MailcapIndex {
+    fn new(entries: Vec<MailcapEntry>) -> MailcapIndex {
+        let mut by_major_type: HashMap<String, Vec<usize>> = HashMap::new();
+        for (index, entry) in entries.iter().enumerate() {
+            by_major_type.entry(major_type(&entry.mime_type).to_string()).or_default().push(index);
+        }
+        MailcapIndex {
+            entries,
+            by_major_type,
+        }
+    }
+
+    // Files with errors or warnings are never cached, so that they are
+    // reported on every run like without the cache
+    fn build(mailcap_paths: &[&Path]) -> Result<MailcapIndex, MailcapError> {
+        let mut file_opened = false;
+        let mut entries = Vec::new();
+
+        for path in mailcap_paths {
+            let mut warnings = Vec::new();
+            match parse_file(path, ParseMode::Lenient, &mut warnings) {
+                Ok(file_entries) => {
+                    if !warnings.is_empty() {
+                        return Err(warnings.remove(0));
+                    }
+                    file_opened = true;
+                    entries.extend(file_entries);
+                },
+                Err(MailcapError { reason: Reason::Io(ref e), line: 0, .. }) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        if file_opened {
+            Ok(MailcapIndex::new(entries))
+        } else {
+            Err(MailcapError {
+                path: PathBuf::new(),
+                line: 0,
+                reason: Reason::NoMailcapFile,
+            })
+        }
+    }
+
+    fn serialize(&self) -> Vec<String> {
+        let mut data = Vec::new();
+        for entry in &self.entries {
+            data.push(format!("entry {}", entry.line));
+            data.push(format!("type {}", entry.mime_type));
+            let fields = [
+                ("view", &entry.view),
+                ("edit", &entry.edit),
+                ("compose", &entry.compose),
+                ("print", &entry.print),
+                ("test", &entry.test),
+                ("nametemplate", &entry.nametemplate),
+            ];
+            for &(key, value) in fields.iter() {
+                if !value.is_empty() {
+                    data.push(format!("{} {}", key, value));
+                }
+            }
+            if entry.needsterminal {
+                data.push(String::from("needsterminal"));
+            }
+            if entry.copiousoutput {
+                data.push(String::from("copiousoutput"));
+            }
+        }
+        data
+    }
+
+    fn deserialize(data: &[String]) -> Option<MailcapIndex> {
+        let mut entries: Vec<MailcapEntry> = Vec::new();
+        for line in data {
+            let mut items = line.splitn(2, ' ');
+            let key = items.next()?;
+            let value = items.next();
+            if key == "entry" {
+                entries.push(MailcapEntry {
+                    line: value?.parse().ok()?,
+                    ..Default::default()
+                });
+                continue;
+            }
+            let entry = entries.last_mut()?;
+            match (key, value) {
+                ("type", Some(value)) => entry.mime_type = value.to_string(),
+                ("view", Some(value)) => entry.view = value.to_string(),
+                ("edit", Some(value)) => entry.edit = value.to_string(),
+                ("compose", Some(value)) => entry.compose = value.to_string(),
+                ("print", Some(value)) => entry.print = value.to_string(),
+                ("test", Some(value)) => entry.test = value.to_string(),
+                ("nametemplate", Some(value)) => entry.nametemplate = value.to_string(),
+                ("needsterminal", None) => entry.needsterminal = true,
+                ("copiousoutput", None) => entry.copiousoutput = true,
+                _ => return None,
+            }
+        }
+        Some(MailcapIndex::new(entries))
+    }
+
+    pub fn load(cache_dir: &Path, mailcap_paths: &[&Path]) -> Result<MailcapIndex, MailcapError> {
+        let stamps = source_stamps(mailcap_paths);
+        let cache_path = cache_dir.join("mailcap");
+
+        if let Some(ref stamps) = stamps {
+            if let Some(index) = read_cache(&cache_path, stamps).and_then(|data| MailcapIndex::deserialize(&data)) {
+                return Ok(index);
+            }
+        }
+
+        let index = MailcapIndex::build(mailcap_paths);
+        match (index, stamps) {
+            (Ok(index), Some(stamps)) => {
+                // The cache is only an optimization, so failing to write it is fine
+                let _ = write_cache(&cache_path, &stamps, &index.serialize());
+                Ok(index)
+            },
+            (index, _) => {
+                let _ = fs::remove_file(&cache_path);
+                index
+            },
+        }
+    }
+
+    // Same result as mailcap::get_entries with the files, the index was
+    // built from
+    pub fn get_entries(&self, mime_types: &[&str]) -> Vec<MailcapEntry> {
+        let mut candidates: Vec<usize> = mime_types.iter()
+            .flat_map(|mime_type| [major_type(mime_type), "*"])
+            .filter_map(|major_type| self.by_major_type.get(major_type))
+            .flatten()
+            .cloned()
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+
+        let entry_types: Vec<&str> = candidates.iter()
+            .map(|&index| self.entries[index].mime_type.as_str())
+            .collect();
+        lookup_order(&entry_types, mime_types).into_iter()
+            .map(|position| self.entries[candidates[position]].clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::mimetype;
+    use super::super::tempfile::TempDir;
+
+    fn data_path(name: &str) -> PathBuf {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data");
+        path.push(name);
+        path
+    }
+
+    #[test]
+    fn test_mime_types_index() {
+        let cache = TempDir::new().unwrap();
+        let path = data_path("mime.types");
+        let mime_paths: [&Path; 1] = [path.as_path()];
+
+        let built = MimeTypesIndex::load(cache.path(), &mime_paths).unwrap();
+        assert!(cache.path().join("mime.types").exists());
+        let cached = MimeTypesIndex::load(cache.path(), &mime_paths).unwrap();
+        assert_eq!(built, cached);
+
+        for filename in &["test.mp4", "test.MP4", "test.txt", "test", "test.html", "archive.tar.gz", "archive.gz", ".txt", "dir.txt/test"] {
+            assert_eq!(cached.get_type_by_extension(filename).unwrap(), mimetype::get_type_by_extension(&mime_paths, filename).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_mime_types_index_nonexistant_file() {
+        let cache = TempDir::new().unwrap();
+        let path = data_path("mime.types.");
+        let mime_paths: [&Path; 1] = [path.as_path()];
+
+        let index = MimeTypesIndex::load(cache.path(), &mime_paths).unwrap();
+        assert_eq!(index.get_type_by_extension("test.mp4").unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(index.get_type_by_extension("test").unwrap(), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_mailcap_index() {
+        let cache = TempDir::new().unwrap();
+        let sources = TempDir::new().unwrap();
+        let (wildcard_path, mut file) = sources.create_file("mailcap-wildcard").unwrap();
+        writeln!(file, "*/*; hexdump '%s'\ntext/*; cat '%s'\nvideo; mplayer '%s'\n*/mp4; mpv '%s'").unwrap();
+        let path = data_path("mailcap");
+        let debian_path = data_path("mailcap-debian");
+        let mailcap_paths: [&Path; 3] = [path.as_path(), wildcard_path.as_path(), debian_path.as_path()];
+
+        let built = MailcapIndex::load(cache.path(), &mailcap_paths).unwrap();
+        assert!(cache.path().join("mailcap").exists());
+        let cached = MailcapIndex::load(cache.path(), &mailcap_paths).unwrap();
+        assert_eq!(built, cached);
+
+        let hierarchies: [&[&str]; 5] = [
+            &["text/plain", "application/octet-stream"],
+            &["text/x-shellscript", "text/plain", "application/octet-stream"],
+            &["application/xhtml+xml", "application/xml", "text/html", "text/plain", "application/octet-stream"],
+            &["image/png", "application/octet-stream"],
+            &["video/mp4", "application/octet-stream"],
+        ];
+        for mime_types in hierarchies.iter() {
+            let mut warnings = Vec::new();
+            let expected = get_entries(&mailcap_paths, mime_types, ParseMode::Strict, &mut warnings).unwrap();
+            assert!(!expected.is_empty());
+            assert_eq!(cached.get_entries(mime_types), expected);
+        }
+    }
+
+    #[test]
+    fn test_mailcap_index_rebuild() {
+        let cache = TempDir::new().unwrap();
+        let sources = TempDir::new().unwrap();
+        let (path, mut file) = sources.create_file("mailcap").unwrap();
+        writeln!(file, "text/plain; cat %s").unwrap();
+        let mailcap_paths: [&Path; 1] = [path.as_path()];
+
+        let index = MailcapIndex::load(cache.path(), &mailcap_paths).unwrap();
+        assert_eq!(index.get_entries(&["text/plain"])[0].view, "cat %s");
+
+        writeln!(file, "text/plain; less %s").unwrap();
+        let index = MailcapIndex::load(cache.path(), &mailcap_paths).unwrap();
+        assert_eq!(index.get_entries(&["text/plain"]).len(), 2);
+
+        writeln!(file, "text/plain; less %s; copiusoutput").unwrap();
+        let err = MailcapIndex::load(cache.path(), &mailcap_paths).unwrap_err();
+        assert_eq!(err.line, 3);
+        assert!(!cache.path().join("mailcap").exists());
+    }
+}
@@ -1,111 +1,552 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
 use regex::Regex;
 
+use run_mailcap_rs::encoding::Encoding;
+use run_mailcap_rs::mailcap::{Action, CommandOptions, InputFile};
+use run_mailcap_rs::mimetype::{Method, METHODS};
+use run_mailcap_rs::terminal::Session;
+
+fn parse_parameters(parameters: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    for parameter in parameters.split(';') {
+        let mut keyvalue = parameter.splitn(2, '=');
+        let key = keyvalue.next().unwrap().trim();
+        if let Some(value) = keyvalue.next() {
+            let value = value.trim();
+            let value = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                &value[1..value.len() - 1]
+            } else {
+                value
+            };
+            result.push((key.to_lowercase(), value.to_string()));
+        }
+    }
+    result
+}
+
+fn expand_tilde(path: &str, home: &Option<String>) -> PathBuf {
+    if let Some(ref home) = *home {
+        if path == "~" {
+            return PathBuf::from(home);
+        }
+        if let Some(path) = path.strip_prefix("~/") {
+            let mut expanded = PathBuf::from(home);
+            expanded.push(path);
+            return expanded;
+        }
+    }
+    PathBuf::from(path)
+}
+
+fn split_paths(paths: &str, home: &Option<String>) -> Vec<PathBuf> {
+    paths.split(':')
+        .filter(|path| !path.is_empty())
+        .map(|path| expand_tilde(path, home))
+        .collect()
+}
+
+fn mailcap_paths(mailcaps: &Option<String>, home: &Option<String>) -> Vec<PathBuf> {
+    match *mailcaps {
+        Some(ref mailcaps) => split_paths(mailcaps, home),
+        None => {
+            let mut paths = Vec::new();
+            if home.is_some() {
+                paths.push(expand_tilde("~/.mailcap", home));
+            }
+            for path in &["/etc/mailcap", "/usr/share/etc/mailcap", "/usr/local/etc/mailcap", "/usr/etc/mailcap"] {
+                paths.push(PathBuf::from(path));
+            }
+            paths
+        },
+    }
+}
+
+fn mime_types_paths(home: &Option<String>) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    if home.is_some() {
+        paths.push(expand_tilde("~/.mime.types", home));
+    }
+    for path in &["/usr/share/etc/mime.types", "/usr/local/etc/mime.types", "/etc/mime.types"] {
+        paths.push(PathBuf::from(path));
+    }
+    paths
+}
+
+fn shared_mime_paths(data_home: &Option<String>, data_dirs: &Option<String>, home: &Option<String>) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    match *data_home {
+        Some(ref data_home) if !data_home.is_empty() => paths.push(PathBuf::from(data_home)),
+        _ => {
+            if home.is_some() {
+                paths.push(expand_tilde("~/.local/share", home));
+            }
+        },
+    }
+    let data_dirs = match *data_dirs {
+        Some(ref data_dirs) if !data_dirs.is_empty() => data_dirs.as_str(),
+        _ => "/usr/local/share:/usr/share",
+    };
+    paths.extend(data_dirs.split(':').filter(|path| !path.is_empty()).map(PathBuf::from));
+    for path in &mut paths {
+        path.push("mime");
+    }
+    paths
+}
+
+fn config_dir(config_home: &Option<String>, home: &Option<String>) -> Option<PathBuf> {
+    let mut path = match *config_home {
+        Some(ref config_home) if !config_home.is_empty() => PathBuf::from(config_home),
+        _ => {
+            home.as_ref()?;
+            expand_tilde("~/.config", home)
+        },
+    };
+    path.push("run-mailcap-rs");
+    Some(path)
+}
+
+fn mime_globs_paths(config_dir: &Option<PathBuf>) -> Vec<PathBuf> {
+    config_dir.iter().map(|dir| dir.join("globs")).collect()
+}
+
+fn terminals_paths(config_dir: &Option<PathBuf>) -> Vec<PathBuf> {
+    config_dir.iter().map(|dir| dir.join("terminals")).collect()
+}
+
+fn cache_dir(cache_home: &Option<String>, home: &Option<String>) -> Option<PathBuf> {
+    let mut path = match *cache_home {
+        Some(ref cache_home) if !cache_home.is_empty() => PathBuf::from(cache_home),
+        _ => {
+            home.as_ref()?;
+            expand_tilde("~/.cache", home)
+        },
+    };
+    path.push("run-mailcap-rs");
+    Some(path)
+}
+
+fn parse_file_argument(re: &Regex, argument: &str) -> InputFile {
+    let mut file = InputFile::default();
+    if let Some(m) = re.captures(argument) {
+        file.filename = m["filename"].to_string();
+        file.mimetype = m["mimetype"].trim().to_string();
+        if let Some(parameters) = m.name("parameters") {
+            file.parameters = parse_parameters(parameters.as_str());
+        }
+    }
+    if file.filename.is_empty() {
+        file.filename = argument.to_string();
+    }
+    file
+}
+
+fn parse_action(value: &str) -> Result<Action, String> {
+    Action::from(value).ok_or_else(|| format!("Invalid action '{}'", value))
+}
+
+fn parse_timeout(value: &str) -> Result<Option<Duration>, String> {
+    match value.parse::<f64>() {
+        Ok(0.0) => Ok(None),
+        Ok(seconds) if seconds > 0.0 && seconds.is_finite() => Ok(Some(Duration::from_secs_f64(seconds))),
+        _ => Err(format!("Invalid timeout '{}'", value)),
+    }
+}
+
+fn parse_detection(value: &str) -> Result<Vec<Method>, String> {
+    let methods: Option<Vec<Method>> = value.split(',')
+        .map(|name| Method::from_name(name.trim()))
+        .collect();
+    match methods {
+        Some(ref methods) if !methods.is_empty() => Ok(methods.clone()),
+        _ => Err(format!("Invalid detection order '{}'", value)),
+    }
+}
+
+// Reads "key = value" lines together with their line numbers; empty lines
+// and lines starting with "#" are ignored
+fn parse_config_file(content: &str) -> Result<Vec<(usize, String, String)>, usize> {
+    let mut settings = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.split_once('=') {
+            Some((key, value)) => settings.push((index + 1, key.trim().to_string(), value.trim().to_string())),
+            None => return Err(index + 1),
+        }
+    }
+    Ok(settings)
+}
+
+// Long name, short name and whether a value is needed
+const OPTIONS: [(&str, Option<char>, bool); 15] = [
+    ("action", Some('a'), true),
+    ("encoding", None, true),
+    ("part", None, true),
+    ("timeout", None, true),
+    ("debug", Some('d'), false),
+    ("nopager", None, false),
+    ("norun", Some('n'), false),
+    ("noshell", None, false),
+    ("usefile", None, false),
+    ("strict", None, false),
+    ("lint", None, false),
+    ("cache", None, false),
+    ("retestfiles", None, false),
+    ("help", Some('h'), false),
+    ("version", Some('V'), false),
+];
+
+// Where the value of a setting came from
 #[derive(Debug)]
+#[derive(Clone, Copy)]
 #[derive(PartialEq)]
-pub enum Action {
-    View,
-    Cat,
-    Edit,
-    Compose,
-    Print,
+pub enum Origin {
+    Default,
+    ConfigFile,
+    Environment,
+    CommandLine,
 }
 
-impl Action {
-    fn from(actionstr: &str) -> Action {
-        match actionstr {
-            "view" => Action::View,
-            "see" => Action::View,
-            "cat" => Action::Cat,
-            "edit" => Action::Edit,
-            "change" => Action::Edit,
-            "compose" => Action::Compose,
-            "create" => Action::Compose,
-            "print" => Action::Print,
-            _ => Action::View,
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Origin::Default => write!(f, "default"),
+            Origin::ConfigFile => write!(f, "config file"),
+            Origin::Environment => write!(f, "environment"),
+            Origin::CommandLine => write!(f, "command line"),
         }
     }
 }
 
 #[derive(Debug)]
 pub struct Config {
-    pub filename: String,
-    pub mimetype: String,
-    pub mimetype_source: String,
+    pub files: Vec<InputFile>,
+    pub parts: Vec<InputFile>,
     pub action: Action,
+    pub encoding: String,
+    pub mailcap_paths: Vec<PathBuf>,
+    pub mime_types_paths: Vec<PathBuf>,
+    pub shared_mime_paths: Vec<PathBuf>,
+    pub mime_globs_paths: Vec<PathBuf>,
+    pub terminals_paths: Vec<PathBuf>,
+    pub cache_dir: Option<PathBuf>,
     pub xtermcmd: String,
+    pub terminal_templates: Vec<(String, String)>,
     pub pager: String,
-    pub running_in_x: bool,
+    pub session: Session,
     pub debug: bool,
     pub nopager: bool,
     pub norun: bool,
+    pub noshell: bool,
+    pub test_timeout: Option<Duration>,
+    pub retestfiles: bool,
+    pub usefile: bool,
+    pub strict: bool,
+    pub lint: bool,
+    pub cache: bool,
+    pub help: bool,
+    pub version: bool,
+    pub detection: Vec<Method>,
+    pub config_file: Option<PathBuf>,
+    pub origins: Vec<(&'static str, Origin)>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
-            filename: String::new(),
-            mimetype: String::new(),
-            mimetype_source: String::new(),
+            files: Vec::new(),
+            parts: Vec::new(),
             action: Action::View,
+            encoding: String::new(),
+            mailcap_paths: Vec::new(),
+            mime_types_paths: Vec::new(),
+            shared_mime_paths: Vec::new(),
+            mime_globs_paths: Vec::new(),
+            terminals_paths: Vec::new(),
+            cache_dir: None,
             xtermcmd: String::from("xterm"),
+            terminal_templates: Vec::new(),
             pager: String::from("less"),
-            running_in_x: false,
+            session: Session::None,
             debug: false,
             nopager: false,
             norun: false,
+            noshell: false,
+            test_timeout: Some(Duration::from_secs(5)),
+            retestfiles: false,
+            usefile: false,
+            strict: false,
+            lint: false,
+            cache: false,
+            help: false,
+            version: false,
+            detection: METHODS.to_vec(),
+            config_file: None,
+            origins: Vec::new(),
         }
     }
 }
 
 impl Config {
-    pub fn parse<IA, IE>(args: IA, envvars: IE) -> Result<Config, &'static str>
+    fn set_origin(&mut self, name: &'static str, origin: Origin) {
+        self.origins.retain(|&(setting, _)| setting != name);
+        self.origins.push((name, origin));
+    }
+
+    pub fn origin(&self, name: &str) -> Origin {
+        self.origins.iter()
+            .find(|&&(setting, _)| setting == name)
+            .map_or(Origin::Default, |&(_, origin)| origin)
+    }
+
+    // The settings, that can be given in the config file, with their values
+    // and where they came from
+    pub fn settings(&self) -> Vec<(&'static str, String, Origin)> {
+        let paths = |paths: &[PathBuf]| paths.iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<String>>()
+            .join(":");
+        let values = vec![
+            ("pager", self.pager.clone()),
+            ("terminal", self.xtermcmd.clone()),
+            ("mailcaps", paths(&self.mailcap_paths)),
+            ("mime-types", paths(&self.mime_types_paths)),
+            ("action", format!("{:?}", self.action).to_lowercase()),
+            ("timeout", self.test_timeout.map_or(String::from("0"), |timeout| timeout.as_secs_f64().to_string())),
+            ("detection", self.detection.iter().map(|method| method.name()).collect::<Vec<&str>>().join(",")),
+        ];
+        values.into_iter().map(|(name, value)| (name, value, self.origin(name))).collect()
+    }
+
+    fn apply_config_file(&mut self, path: &Path, content: &str, home: &Option<String>) -> Result<(), String> {
+        let settings = parse_config_file(content)
+            .map_err(|line| format!("{}:{}: Expected \"key = value\"", path.display(), line))?;
+        for (line, key, value) in settings {
+            let error = |message: String| format!("{}:{}: {}", path.display(), line, message);
+            let name = match key.as_str() {
+                "pager" => {
+                    self.pager = value;
+                    "pager"
+                },
+                "terminal" => {
+                    self.xtermcmd = value;
+                    "terminal"
+                },
+                "mailcaps" => {
+                    self.mailcap_paths = split_paths(&value, home);
+                    "mailcaps"
+                },
+                "mime-types" => {
+                    self.mime_types_paths = split_paths(&value, home);
+                    "mime-types"
+                },
+                "action" => {
+                    self.action = parse_action(&value).map_err(error)?;
+                    "action"
+                },
+                "timeout" => {
+                    self.test_timeout = parse_timeout(&value).map_err(error)?;
+                    "timeout"
+                },
+                "detection" => {
+                    self.detection = parse_detection(&value).map_err(error)?;
+                    "detection"
+                },
+                _ => return Err(error(format!("Unknown setting '{}'", key))),
+            };
+            self.set_origin(name, Origin::ConfigFile);
+        }
+        Ok(())
+    }
+
+    fn apply_option(&mut self, name: &str, value: String, re: &Regex) -> Result<(), String> {
+        match name {
+            "action" => {
+                self.action = parse_action(&value)?;
+                self.set_origin("action", Origin::CommandLine);
+            },
+            "encoding" => {
+                if Encoding::from(&value).is_none() {
+                    return Err(format!("Unsupported encoding '{}'", value));
+                }
+                self.encoding = value;
+            },
+            "part" => {
+                let part = parse_file_argument(re, &value);
+                // Only the files are read from standard input, not the parts
+                if part.filename == "-" {
+                    return Err(String::from("Standard input ('-') can not be given as a part"));
+                }
+                self.parts.push(part);
+            },
+            "timeout" => {
+                self.test_timeout = parse_timeout(&value)?;
+                self.set_origin("timeout", Origin::CommandLine);
+            },
+            "debug" => self.debug = true,
+            "nopager" => self.nopager = true,
+            "norun" => self.norun = true,
+            "noshell" => self.noshell = true,
+            "usefile" => self.usefile = true,
+            "strict" => self.strict = true,
+            "lint" => self.lint = true,
+            "cache" => self.cache = true,
+            "retestfiles" => self.retestfiles = true,
+            "help" => self.help = true,
+            "version" => self.version = true,
+            _ => unreachable!(),
+        }
+        Ok(())
+    }
+
+    pub fn command_options(&self) -> CommandOptions {
+        CommandOptions {
+            action: self.action,
+            pager: self.pager.clone(),
+            nopager: self.nopager,
+            terminal: self.xtermcmd.clone(),
+            terminal_templates: self.terminal_templates.clone(),
+            session: self.session,
+            noshell: self.noshell,
+        }
+    }
+
+    pub fn parse<IA, IE>(args: IA, envvars: IE) -> Result<Config, String>
     where
         IA: IntoIterator<Item = String>,
         IE: IntoIterator<Item = (String, String)>,
     {
         let mut args = args.into_iter();
         let programname = args.next().unwrap();
+        let re = Regex::new(r#"^(?P<mimetype>[^/:;]+/[^/:;]+)(?P<parameters>;(?:[^:"]|"[^"]*")*)?:(?P<filename>.*)"#).unwrap();
         let programname = programname.rsplit('/').next().unwrap();
-        let mut config: Config = Config {
-            action: Action::from(programname),
-            ..Default::default()
-        };
+        let mut config = Config::default();
 
+        let mut pager = None;
+        let mut xtermcmd = None;
+        let mut home = None;
+        let mut mailcaps = None;
+        let mut data_home = None;
+        let mut data_dirs = None;
+        let mut config_home = None;
+        let mut cache_home = None;
+        let (mut tmux, mut screen, mut wayland, mut x11) = (false, false, false, false);
         for (key, value) in envvars {
             match key.as_ref() {
-                "PAGER" => config.pager = value,
-                "XTERMCMD" => config.xtermcmd = value,
-                "DISPLAY" => config.running_in_x = true,
+                "PAGER" => pager = Some(value),
+                "XTERMCMD" => xtermcmd = Some(value),
+                "DISPLAY" => x11 = true,
+                "WAYLAND_DISPLAY" => wayland = true,
+                "TMUX" => tmux = true,
+                "STY" => screen = true,
+                "HOME" => home = Some(value),
+                "MAILCAPS" => mailcaps = Some(value),
+                "XDG_DATA_HOME" => data_home = Some(value),
+                "XDG_DATA_DIRS" => data_dirs = Some(value),
+                "XDG_CONFIG_HOME" => config_home = Some(value),
+                "XDG_CACHE_HOME" => cache_home = Some(value),
                 _ => {},
             }
         };
-        for argument in args {
-            if argument.starts_with("--") {
-                let mut argument_parts = argument.splitn(2, '=');
-                let key = argument_parts.next().unwrap();
-                let value = argument_parts.next().unwrap_or("");
-
-                match key {
-                    "--action" => config.action = Action::from(value),
-                    "--debug" => config.debug = true,
-                    "--nopager" => config.nopager = true,
-                    "--norun" => config.norun = true,
-                    _ => {},
+        config.mailcap_paths = mailcap_paths(&None, &home);
+        config.mime_types_paths = mime_types_paths(&home);
+        config.shared_mime_paths = shared_mime_paths(&data_home, &data_dirs, &home);
+        let config_dir = config_dir(&config_home, &home);
+        config.mime_globs_paths = mime_globs_paths(&config_dir);
+        config.terminals_paths = terminals_paths(&config_dir);
+        config.session = Session::detect(tmux, screen, wayland, x11);
+        config.cache_dir = cache_dir(&cache_home, &home);
+
+        // An invalid config file is only reported after the arguments, so
+        // --help and --version still work with it
+        let mut config_file_error = None;
+        if let Some(path) = config_dir.map(|dir| dir.join("config")) {
+            if let Ok(content) = fs::read_to_string(&path) {
+                match config.apply_config_file(&path, &content, &home) {
+                    Ok(()) => config.config_file = Some(path),
+                    Err(e) => config_file_error = Some(e),
                 }
+            }
+        }
+
+        if let Some(pager) = pager {
+            config.pager = pager;
+            config.set_origin("pager", Origin::Environment);
+        }
+        if let Some(xtermcmd) = xtermcmd {
+            config.xtermcmd = xtermcmd;
+            config.set_origin("terminal", Origin::Environment);
+        }
+        if mailcaps.is_some() {
+            config.mailcap_paths = mailcap_paths(&mailcaps, &home);
+            config.set_origin("mailcaps", Origin::Environment);
+        }
+        if let Some(action) = Action::from(programname) {
+            config.action = action;
+            config.set_origin("action", Origin::CommandLine);
+        }
+
+        let mut options_ended = false;
+        while let Some(argument) = args.next() {
+            if options_ended || argument == "-" || !argument.starts_with('-') {
+                config.files.push(parse_file_argument(&re, &argument));
+            } else if argument == "--" {
+                options_ended = true;
+            } else if let Some(option) = argument.strip_prefix("--") {
+                let (name, value) = match option.split_once('=') {
+                    Some((name, value)) => (name, Some(value.to_string())),
+                    None => (option, None),
+                };
+                let needs_value = match OPTIONS.iter().find(|&&(long, _, _)| long == name) {
+                    Some(&(_, _, needs_value)) => needs_value,
+                    None => return Err(format!("Unknown option '--{}'", name)),
+                };
+                let value = match (needs_value, value) {
+                    (true, Some(value)) => value,
+                    (true, None) => args.next().ok_or_else(|| format!("Option '--{}' needs a value", name))?,
+                    (false, Some(_)) => return Err(format!("Option '--{}' does not take a value", name)),
+                    (false, None) => String::new(),
+                };
+                config.apply_option(name, value, &re)?;
             } else {
-                let re = Regex::new(r"^(?P<mimetype>[^/:]+/[^/:]+):(?P<filename>.*)").unwrap();
-                if let Some(m) = re.captures(&argument) {
-                    config.filename = m["filename"].to_string();
-                    config.mimetype = m["mimetype"].to_string();
-                }
-                if config.filename == "" {
-                    config.filename = argument;
+                // Short options can be combined, the last one may be followed
+                // by its value
+                let mut chars = argument[1..].chars();
+                while let Some(c) = chars.next() {
+                    let (name, needs_value) = match OPTIONS.iter().find(|&&(_, short, _)| short == Some(c)) {
+                        Some(&(long, _, needs_value)) => (long, needs_value),
+                        None => return Err(format!("Unknown option '-{}'", c)),
+                    };
+                    let value = if needs_value {
+                        let rest: String = chars.by_ref().collect();
+                        if rest.is_empty() {
+                            args.next().ok_or_else(|| format!("Option '-{}' needs a value", c))?
+                        } else {
+                            rest
+                        }
+                    } else {
+                        String::new()
+                    };
+                    config.apply_option(name, value, &re)?;
                 }
             }
         }
 
-        if config.filename == "" {
-            Err("No filename was given in arguments")
+        if config.help || config.version {
+            Ok(config)
+        } else if let Some(err) = config_file_error {
+            Err(err)
+        } else if config.files.iter().filter(|file| file.filename == "-").count() > 1 {
+            // Standard input can only be read once
+            Err(String::from("Standard input ('-') can only be given once"))
+        } else if config.files.is_empty() && !config.lint {
+            Err(String::from("No file name was given"))
         } else {
             Ok(config)
         }
@@ -141,11 +582,11 @@ mod tests {
         let env = Vec::new();
         let config = Config::parse(args, env).unwrap();
 
-        assert_eq!(config.filename, "test.txt");
+        assert_eq!(config.files[0].filename, "test.txt");
         assert_eq!(config.action, Action::View);
         assert_eq!(config.xtermcmd, "xterm");
         assert_eq!(config.pager, "less");
-        assert_eq!(config.running_in_x, false);
+        assert_eq!(config.session, Session::None);
     }
 
     #[test]
@@ -158,7 +599,7 @@ mod tests {
         let env = Vec::new();
         let config = Config::parse(args, env).unwrap();
 
-        assert_eq!(config.filename, "test.txt");
+        assert_eq!(config.files[0].filename, "test.txt");
         assert_eq!(config.action, Action::Edit);
     }
 
@@ -171,7 +612,7 @@ mod tests {
         let env = Vec::new();
         let config = Config::parse(args, env).unwrap();
 
-        assert_eq!(config.filename, "test.txt");
+        assert_eq!(config.files[0].filename, "test.txt");
         assert_eq!(config.action, Action::Compose);
     }
 
@@ -184,7 +625,7 @@ mod tests {
         let env = Vec::new();
         let config = Config::parse(args, env).unwrap();
 
-        assert_eq!(config.filename, "test.txt");
+        assert_eq!(config.files[0].filename, "test.txt");
         assert_eq!(config.action, Action::Compose);
     }
 
@@ -198,10 +639,37 @@ mod tests {
         let env = Vec::new();
         let config = Config::parse(args, env).unwrap();
 
-        assert_eq!(config.filename, "test.txt");
+        assert_eq!(config.files[0].filename, "test.txt");
         assert_eq!(config.action, Action::Edit);
     }
 
+    #[test]
+    fn test_config_encoding_in_args() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("--encoding=gzip"),
+            String::from("test.txt"),
+        ];
+        let env = Vec::new();
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.files[0].filename, "test.txt");
+        assert_eq!(config.encoding, "gzip");
+    }
+
+    #[test]
+    fn test_config_lint_without_filename() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("--lint"),
+        ];
+        let env = Vec::new();
+        let config = Config::parse(args, env).unwrap();
+
+        assert!(config.lint);
+        assert!(config.files.is_empty());
+    }
+
     #[test]
     fn test_config_xtermcmd_from_env() {
         let args = vec![
@@ -217,17 +685,28 @@ mod tests {
     }
 
     #[test]
-    fn test_config_running_in_x_from_env() {
+    fn test_config_session_from_env() {
         let args = vec![
             String::from("run-mailcap-rs"),
             String::from("test.txt"),
         ];
-        let env = vec![
+        let mut env = vec![
             (String::from("DISPLAY"), String::from(":0")),
         ];
-        let config = Config::parse(args, env).unwrap();
+        let config = Config::parse(args.clone(), env.clone()).unwrap();
+        assert_eq!(config.session, Session::X11);
 
-        assert_eq!(config.running_in_x, true);
+        env.push((String::from("WAYLAND_DISPLAY"), String::from("wayland-0")));
+        let config = Config::parse(args.clone(), env.clone()).unwrap();
+        assert_eq!(config.session, Session::Wayland);
+
+        env.push((String::from("STY"), String::from("1234.pts-0.host")));
+        let config = Config::parse(args.clone(), env.clone()).unwrap();
+        assert_eq!(config.session, Session::Screen);
+
+        env.push((String::from("TMUX"), String::from("/tmp/tmux-1000/default,1234,0")));
+        let config = Config::parse(args, env).unwrap();
+        assert_eq!(config.session, Session::Tmux);
     }
 
     #[test]
@@ -244,6 +723,342 @@ mod tests {
         assert_eq!(config.pager, "more");
     }
 
+    #[test]
+    fn test_config_default_mailcap_paths() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("HOME"), String::from("/home/user")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.mailcap_paths, vec![
+            PathBuf::from("/home/user/.mailcap"),
+            PathBuf::from("/etc/mailcap"),
+            PathBuf::from("/usr/share/etc/mailcap"),
+            PathBuf::from("/usr/local/etc/mailcap"),
+            PathBuf::from("/usr/etc/mailcap"),
+        ]);
+    }
+
+    #[test]
+    fn test_config_mailcap_paths_from_env() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("MAILCAPS"), String::from("~/.config/mailcap::/etc/mailcap:~")),
+            (String::from("HOME"), String::from("/home/user")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.mailcap_paths, vec![
+            PathBuf::from("/home/user/.config/mailcap"),
+            PathBuf::from("/etc/mailcap"),
+            PathBuf::from("/home/user"),
+        ]);
+    }
+
+    #[test]
+    fn test_config_mailcap_paths_without_home() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("MAILCAPS"), String::from("~/.mailcap:/etc/mailcap")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.mailcap_paths, vec![
+            PathBuf::from("~/.mailcap"),
+            PathBuf::from("/etc/mailcap"),
+        ]);
+    }
+
+    #[test]
+    fn test_config_default_shared_mime_paths() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("HOME"), String::from("/home/user")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.shared_mime_paths, vec![
+            PathBuf::from("/home/user/.local/share/mime"),
+            PathBuf::from("/usr/local/share/mime"),
+            PathBuf::from("/usr/share/mime"),
+        ]);
+    }
+
+    #[test]
+    fn test_config_shared_mime_paths_from_env() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("HOME"), String::from("/home/user")),
+            (String::from("XDG_DATA_HOME"), String::from("/home/user/data")),
+            (String::from("XDG_DATA_DIRS"), String::from("/opt/share::/usr/share")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.shared_mime_paths, vec![
+            PathBuf::from("/home/user/data/mime"),
+            PathBuf::from("/opt/share/mime"),
+            PathBuf::from("/usr/share/mime"),
+        ]);
+    }
+
+    #[test]
+    fn test_config_mime_globs_paths() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("HOME"), String::from("/home/user")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.mime_globs_paths, vec![
+            PathBuf::from("/home/user/.config/run-mailcap-rs/globs"),
+        ]);
+        assert_eq!(config.terminals_paths, vec![
+            PathBuf::from("/home/user/.config/run-mailcap-rs/terminals"),
+        ]);
+
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("HOME"), String::from("/home/user")),
+            (String::from("XDG_CONFIG_HOME"), String::from("/home/user/conf")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.mime_globs_paths, vec![
+            PathBuf::from("/home/user/conf/run-mailcap-rs/globs"),
+        ]);
+        assert_eq!(config.terminals_paths, vec![
+            PathBuf::from("/home/user/conf/run-mailcap-rs/terminals"),
+        ]);
+    }
+
+    #[test]
+    fn test_config_cache_dir() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("--cache"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("HOME"), String::from("/home/user")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert!(config.cache);
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/home/user/.cache/run-mailcap-rs")));
+
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("XDG_CACHE_HOME"), String::from("/tmp/cache")),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert!(!config.cache);
+        assert_eq!(config.cache_dir, Some(PathBuf::from("/tmp/cache/run-mailcap-rs")));
+
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let config = Config::parse(args, Vec::new()).unwrap();
+
+        assert_eq!(config.cache_dir, None);
+    }
+
+    fn config_home(name: &str) -> (String, String) {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data");
+        path.push(name);
+        (String::from("XDG_CONFIG_HOME"), path.to_string_lossy().into_owned())
+    }
+
+    #[test]
+    fn test_config_file() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("HOME"), String::from("/home/user")),
+            config_home("config"),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert!(config.config_file.as_ref().unwrap().ends_with("tests/data/config/run-mailcap-rs/config"));
+        assert_eq!(config.pager, "most");
+        assert_eq!(config.xtermcmd, "foot");
+        assert_eq!(config.mailcap_paths, vec![PathBuf::from("/home/user/.mailcap"), PathBuf::from("/etc/mailcap")]);
+        assert_eq!(config.mime_types_paths, vec![PathBuf::from("/home/user/.mime.types")]);
+        assert_eq!(config.action, Action::Edit);
+        assert_eq!(config.test_timeout, Some(Duration::from_millis(2500)));
+        assert_eq!(config.detection, vec![Method::Content, Method::UserGlobs, Method::MimeTypes]);
+        assert_eq!(config.origin("pager"), Origin::ConfigFile);
+        assert_eq!(config.origin("detection"), Origin::ConfigFile);
+    }
+
+    #[test]
+    fn test_config_file_precedence() {
+        let args = vec![
+            String::from("/usr/bin/see"),
+            String::from("--timeout=1"),
+            String::from("test.txt"),
+        ];
+        let env = vec![
+            (String::from("PAGER"), String::from("less -R")),
+            (String::from("MAILCAPS"), String::from("/etc/mailcap")),
+            config_home("config"),
+        ];
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.pager, "less -R");
+        assert_eq!(config.origin("pager"), Origin::Environment);
+        assert_eq!(config.xtermcmd, "foot");
+        assert_eq!(config.origin("terminal"), Origin::ConfigFile);
+        assert_eq!(config.mailcap_paths, vec![PathBuf::from("/etc/mailcap")]);
+        assert_eq!(config.origin("mailcaps"), Origin::Environment);
+        assert_eq!(config.action, Action::View);
+        assert_eq!(config.origin("action"), Origin::CommandLine);
+        assert_eq!(config.test_timeout, Some(Duration::from_secs(1)));
+        assert_eq!(config.origin("timeout"), Origin::CommandLine);
+
+        let settings = config.settings();
+        assert_eq!(settings[0], ("pager", String::from("less -R"), Origin::Environment));
+        assert_eq!(settings[5], ("timeout", String::from("1"), Origin::CommandLine));
+        assert_eq!(settings[6], ("detection", String::from("content,globs,mime.types"), Origin::ConfigFile));
+    }
+
+    #[test]
+    fn test_config_file_invalid() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("test.txt"),
+        ];
+        let err = Config::parse(args.clone(), vec![config_home("config-invalid")]).unwrap_err();
+        assert!(err.ends_with("tests/data/config-invalid/run-mailcap-rs/config:2: Unknown setting 'colour'"), "{}", err);
+
+        for option in &["--help", "--version"] {
+            let args = vec![String::from("run-mailcap-rs"), option.to_string()];
+            assert!(Config::parse(args, vec![config_home("config-invalid")]).is_ok());
+        }
+
+        let config = Config::parse(args, vec![config_home("nonexistant")]).unwrap();
+        assert!(config.config_file.is_none());
+        assert_eq!(config.pager, "less");
+        assert_eq!(config.origin("pager"), Origin::Default);
+    }
+
+    #[test]
+    fn test_parse_config_file() {
+        assert_eq!(parse_config_file("# comment\n\n pager = less -R \naction=edit").unwrap(), vec![
+            (3, String::from("pager"), String::from("less -R")),
+            (4, String::from("action"), String::from("edit")),
+        ]);
+        assert_eq!(parse_config_file("# comment\npager"), Err(2));
+        assert!(parse_detection("content,magic").is_err());
+        assert!(parse_detection("").is_err());
+    }
+
+    fn parse_args(args: &[&str]) -> Result<Config, String> {
+        let mut arguments = vec![String::from("run-mailcap-rs")];
+        arguments.extend(args.iter().map(|arg| arg.to_string()));
+        Config::parse(arguments, Vec::new())
+    }
+
+    #[test]
+    fn test_config_invalid_args() {
+        assert_eq!(parse_args(&["--foo", "test.txt"]).unwrap_err(), "Unknown option '--foo'");
+        assert_eq!(parse_args(&["-x", "test.txt"]).unwrap_err(), "Unknown option '-x'");
+        assert_eq!(parse_args(&["-dx", "test.txt"]).unwrap_err(), "Unknown option '-x'");
+        assert_eq!(parse_args(&["--action=bogus", "test.txt"]).unwrap_err(), "Invalid action 'bogus'");
+        assert_eq!(parse_args(&["--encoding=zip", "test.txt"]).unwrap_err(), "Unsupported encoding 'zip'");
+        assert_eq!(parse_args(&["--timeout=-1", "test.txt"]).unwrap_err(), "Invalid timeout '-1'");
+        assert_eq!(parse_args(&["--debug=yes", "test.txt"]).unwrap_err(), "Option '--debug' does not take a value");
+        assert_eq!(parse_args(&["test.txt", "--action"]).unwrap_err(), "Option '--action' needs a value");
+        assert_eq!(parse_args(&["test.txt", "-a"]).unwrap_err(), "Option '-a' needs a value");
+        assert_eq!(parse_args(&["--debug"]).unwrap_err(), "No file name was given");
+    }
+
+    #[test]
+    fn test_config_option_forms() {
+        let config = parse_args(&["--action", "edit", "-dn", "test.txt"]).unwrap();
+        assert_eq!(config.action, Action::Edit);
+        assert!(config.debug);
+        assert!(config.norun);
+
+        assert_eq!(parse_args(&["-aprint", "test.txt"]).unwrap().action, Action::Print);
+        assert_eq!(parse_args(&["-da", "cat", "test.txt"]).unwrap().action, Action::Cat);
+
+        let config = parse_args(&["-d", "--", "--debug", "-", "-n"]).unwrap();
+        assert!(config.debug);
+        assert!(!config.norun);
+        let filenames: Vec<&str> = config.files.iter().map(|file| file.filename.as_str()).collect();
+        assert_eq!(filenames, vec!["--debug", "-", "-n"]);
+    }
+
+    #[test]
+    fn test_config_help_and_version() {
+        assert!(parse_args(&["--help"]).unwrap().help);
+        assert!(parse_args(&["-h", "--foo"]).is_err());
+        assert!(parse_args(&["-h"]).unwrap().help);
+        assert!(parse_args(&["--version"]).unwrap().version);
+        assert!(parse_args(&["-V"]).unwrap().version);
+    }
+
+    #[test]
+    fn test_config_timeout_in_args() {
+        let config = Config::parse(vec![String::from("run-mailcap-rs"), String::from("test.txt")], Vec::new()).unwrap();
+        assert_eq!(config.test_timeout, Some(Duration::from_secs(5)));
+
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("--timeout=0.5"),
+            String::from("test.txt"),
+        ];
+        let config = Config::parse(args, Vec::new()).unwrap();
+        assert_eq!(config.test_timeout, Some(Duration::from_millis(500)));
+
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("--timeout=0"),
+            String::from("test.txt"),
+        ];
+        let config = Config::parse(args, Vec::new()).unwrap();
+        assert_eq!(config.test_timeout, None);
+
+        for timeout in &["--timeout", "--timeout=-1", "--timeout=foo", "--timeout=inf"] {
+            let args = vec![
+                String::from("run-mailcap-rs"),
+                timeout.to_string(),
+                String::from("test.txt"),
+            ];
+            assert!(Config::parse(args, Vec::new()).is_err());
+        }
+    }
+
     #[test]
     fn test_config_mimetype_from_args() {
         let args = vec![
@@ -254,7 +1069,54 @@ mod tests {
 
         let config = Config::parse(args, env).unwrap();
 
-        assert_eq!(config.mimetype, "text/plain");
+        assert_eq!(config.files[0].mimetype, "text/plain");
+    }
+
+    #[test]
+    fn test_config_mimetype_parameters_from_args() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("text/plain; charset=iso-8859-1; Format=\"flowed\":test.txt"),
+        ];
+        let env = Vec::new();
+
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.files[0].filename, "test.txt");
+        assert_eq!(config.files[0].mimetype, "text/plain");
+        assert_eq!(config.files[0].parameters, vec![
+            (String::from("charset"), String::from("iso-8859-1")),
+            (String::from("format"), String::from("flowed")),
+        ]);
+
+        // A quoted parameter value can contain a colon
+        let config = parse_args(&["text/plain; name=\"a:b\":test:1.txt"]).unwrap();
+        assert_eq!(config.files[0].filename, "test:1.txt");
+        assert_eq!(config.files[0].parameters, vec![(String::from("name"), String::from("a:b"))]);
+    }
+
+    #[test]
+    fn test_config_parts_in_args() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("--part=text/plain:part1.txt"),
+            String::from("--part=image/png:part2.png"),
+            String::from("--part=part3.pdf"),
+            String::from("multipart/mixed:message"),
+        ];
+        let env = Vec::new();
+
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.files.len(), 1);
+        assert_eq!(config.files[0].filename, "message");
+        assert_eq!(config.parts.len(), 3);
+        assert_eq!(config.parts[0].filename, "part1.txt");
+        assert_eq!(config.parts[0].mimetype, "text/plain");
+        assert_eq!(config.parts[1].filename, "part2.png");
+        assert_eq!(config.parts[1].mimetype, "image/png");
+        assert_eq!(config.parts[2].filename, "part3.pdf");
+        assert_eq!(config.parts[2].mimetype, "");
     }
 
     #[test]
@@ -267,7 +1129,46 @@ mod tests {
 
         let config = Config::parse(args, env).unwrap();
 
-        assert_eq!(config.filename, "test:foo.txt");
+        assert_eq!(config.files[0].filename, "test:foo.txt");
+    }
+
+    #[test]
+    fn test_config_stdin() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("text/html:-"),
+        ];
+        let env = Vec::new();
+
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.files[0].filename, "-");
+        assert_eq!(config.files[0].mimetype, "text/html");
+
+        assert_eq!(parse_args(&["-", "test.txt", "text/plain:-"]).unwrap_err(), "Standard input ('-') can only be given once");
+        assert_eq!(parse_args(&["--part=-", "test.txt"]).unwrap_err(), "Standard input ('-') can not be given as a part");
+        assert_eq!(parse_args(&["--part", "text/plain:-", "test.txt"]).unwrap_err(), "Standard input ('-') can not be given as a part");
+    }
+
+    #[test]
+    fn test_config_multiple_files() {
+        let args = vec![
+            String::from("run-mailcap-rs"),
+            String::from("foo.pdf"),
+            String::from("text/plain:bar.xml"),
+            String::from("baz.txt"),
+        ];
+        let env = Vec::new();
+
+        let config = Config::parse(args, env).unwrap();
+
+        assert_eq!(config.files.len(), 3);
+        assert_eq!(config.files[0].filename, "foo.pdf");
+        assert_eq!(config.files[0].mimetype, "");
+        assert_eq!(config.files[1].filename, "bar.xml");
+        assert_eq!(config.files[1].mimetype, "text/plain");
+        assert_eq!(config.files[2].filename, "baz.txt");
+        assert_eq!(config.files[2].mimetype, "");
     }
 }
 
@@ -0,0 +1,136 @@
+use std::fs::File;
+use std::io;
+use std::process::{Command, Stdio};
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Encoding {
+    pub fn from(encodingstr: &str) -> Option<Encoding> {
+        match encodingstr {
+            "gzip" | "x-gzip" | "gz" => Some(Encoding::Gzip),
+            "bzip2" | "x-bzip2" | "bz2" => Some(Encoding::Bzip2),
+            "xz" | "x-xz" => Some(Encoding::Xz),
+            "zstd" | "x-zstd" | "zst" => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+
+    fn program(&self) -> &'static str {
+        match *self {
+            Encoding::Gzip => "gzip",
+            Encoding::Bzip2 => "bzip2",
+            Encoding::Xz => "xz",
+            Encoding::Zstd => "zstd",
+        }
+    }
+}
+
+pub fn get_encoding_by_extension(filename: &str) -> Option<(Encoding, &str)> {
+    let mut parts = filename.rsplitn(2, '.');
+    let extension = parts.next().unwrap();
+    let basename = parts.next()?;
+
+    if basename.is_empty() || basename.ends_with('/') {
+        return None;
+    }
+    let encoding = match extension.to_lowercase().as_ref() {
+        "gz" => Encoding::Gzip,
+        "bz2" => Encoding::Bzip2,
+        "xz" => Encoding::Xz,
+        "zst" => Encoding::Zstd,
+        _ => return None,
+    };
+    Some((encoding, basename))
+}
+
+pub fn strip_extension<'a>(encoding: &Encoding, filename: &'a str) -> &'a str {
+    match get_encoding_by_extension(filename) {
+        Some((ref found, basename)) if found == encoding => basename,
+        _ => filename,
+    }
+}
+
+pub fn decompress(encoding: &Encoding, filename: &str, output: File) -> Result<(), io::Error> {
+    let input = File::open(filename)?;
+    let status = Command::new(encoding.program())
+        .arg("-d")
+        .arg("-c")
+        .stdin(input)
+        .stdout(output)
+        .stderr(Stdio::null())
+        .status()?;
+
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} failed to decompress the file", encoding.program())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use super::*;
+    use super::super::tempfile::TempDir;
+
+    #[test]
+    fn test_encoding_from() {
+        assert_eq!(Encoding::from("gzip"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::from("x-gzip"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::from("bzip2"), Some(Encoding::Bzip2));
+        assert_eq!(Encoding::from("xz"), Some(Encoding::Xz));
+        assert_eq!(Encoding::from("zstd"), Some(Encoding::Zstd));
+        assert_eq!(Encoding::from("base64"), None);
+    }
+
+    #[test]
+    fn test_encoding_by_extension() {
+        assert_eq!(get_encoding_by_extension("report.pdf.gz"), Some((Encoding::Gzip, "report.pdf")));
+        assert_eq!(get_encoding_by_extension("report.pdf.GZ"), Some((Encoding::Gzip, "report.pdf")));
+        assert_eq!(get_encoding_by_extension("dir/report.pdf.bz2"), Some((Encoding::Bzip2, "dir/report.pdf")));
+        assert_eq!(get_encoding_by_extension("report.pdf.xz"), Some((Encoding::Xz, "report.pdf")));
+        assert_eq!(get_encoding_by_extension("report.pdf.zst"), Some((Encoding::Zstd, "report.pdf")));
+        assert_eq!(get_encoding_by_extension("report.pdf"), None);
+        assert_eq!(get_encoding_by_extension("report.gzip"), None);
+        assert_eq!(get_encoding_by_extension("gz"), None);
+        assert_eq!(get_encoding_by_extension(".gz"), None);
+        assert_eq!(get_encoding_by_extension("dir/.gz"), None);
+    }
+
+    #[test]
+    fn test_encoding_strip_extension() {
+        assert_eq!(strip_extension(&Encoding::Gzip, "report.pdf.gz"), "report.pdf");
+        assert_eq!(strip_extension(&Encoding::Xz, "report.pdf.gz"), "report.pdf.gz");
+        assert_eq!(strip_extension(&Encoding::Gzip, "report.pdf"), "report.pdf");
+    }
+
+    #[test]
+    fn test_encoding_decompress() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/compressed.txt.gz");
+
+        let tempdir = TempDir::new().unwrap();
+        let (output_path, output) = tempdir.create_file("compressed.txt").unwrap();
+        decompress(&Encoding::Gzip, path.to_str().unwrap(), output).unwrap();
+        assert_eq!(fs::read_to_string(output_path).unwrap(), "hello\n");
+    }
+
+    #[test]
+    fn test_encoding_decompress_invalid_data() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mime.types");
+
+        let tempdir = TempDir::new().unwrap();
+        let (_output_path, output) = tempdir.create_file("mime.types").unwrap();
+        let err = decompress(&Encoding::Gzip, path.to_str().unwrap(), output).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,134 @@
+fn match_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut index = 0;
+    let negated = match pattern.first() {
+        Some(&'!') | Some(&'^') => {
+            index += 1;
+            true
+        },
+        _ => false,
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    while index < pattern.len() {
+        let start = pattern[index];
+        if start == ']' && !first {
+            return Some((matched != negated, index + 1));
+        }
+        first = false;
+        if index + 2 < pattern.len() && pattern[index + 1] == '-' && pattern[index + 2] != ']' {
+            if start <= c && c <= pattern[index + 2] {
+                matched = true;
+            }
+            index += 3;
+        } else {
+            if start == c {
+                matched = true;
+            }
+            index += 1;
+        }
+    }
+    None
+}
+
+// The length of the pattern element at the start of the pattern, if it
+// matches the character
+fn match_element(pattern: &[char], c: char) -> Option<usize> {
+    match pattern[0] {
+        '?' => Some(1),
+        '[' => match match_class(&pattern[1..], c) {
+            Some((true, length)) => Some(1 + length),
+            Some((false, _)) => None,
+            None if c == '[' => Some(1),
+            None => None,
+        },
+        p if p == c => Some(1),
+        _ => None,
+    }
+}
+
+// On a mismatch, only the last "*" takes one more character, earlier ones
+// never have to, so the time is bounded by the product of the lengths
+fn match_chars(pattern: &[char], name: &[char]) -> bool {
+    let (mut p, mut n) = (0, 0);
+    let mut last_star = None;
+    while n < name.len() {
+        if p < pattern.len() && pattern[p] == '*' {
+            last_star = Some((p, n));
+            p += 1;
+            continue;
+        }
+        let matched = if p < pattern.len() { match_element(&pattern[p..], name[n]) } else { None };
+        if let Some(length) = matched {
+            p += length;
+            n += 1;
+            continue;
+        }
+        match last_star {
+            Some((star, skipped)) => {
+                last_star = Some((star, skipped + 1));
+                p = star + 1;
+                n = skipped + 1;
+            },
+            None => return false,
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+pub fn glob_match(pattern: &str, name: &str, case_sensitive: bool) -> bool {
+    let (pattern, name) = if case_sensitive {
+        (pattern.chars().collect::<Vec<char>>(), name.chars().collect::<Vec<char>>())
+    } else {
+        (pattern.to_lowercase().chars().collect(), name.to_lowercase().chars().collect())
+    };
+    match_chars(&pattern, &name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.pdf", "test.pdf", true));
+        assert!(!glob_match("*.pdf", "test.PDF", true));
+        assert!(glob_match("*.pdf", "test.PDF", false));
+        assert!(glob_match("*.tar.gz", "archive.tar.gz", true));
+        assert!(!glob_match("*.tar.gz", "archive.gz", true));
+        assert!(glob_match("Makefile", "Makefile", true));
+        assert!(!glob_match("Makefile", "Makefile.am", true));
+        assert!(glob_match("README*", "README.md", true));
+        assert!(glob_match("*.[ch]", "main.c", true));
+        assert!(glob_match("*.[ch]", "main.h", true));
+        assert!(!glob_match("*.[ch]", "main.o", true));
+        assert!(glob_match("*.[!o]", "main.c", true));
+        assert!(!glob_match("*.[!o]", "main.o", true));
+        assert!(glob_match("*.[0-9]", "man.1", true));
+        assert!(glob_match("?akefile", "makefile", true));
+        assert!(!glob_match("?akefile", "akefile", true));
+        assert!(glob_match("*[]]", "foo]", true));
+        assert!(glob_match("*[", "foo[", true));
+        assert!(glob_match("*", "", true));
+        assert!(glob_match("*a*b", "xaxxb", true));
+        assert!(!glob_match("a*", "", true));
+    }
+
+    #[test]
+    fn test_glob_match_many_stars() {
+        let name = "a".repeat(200);
+        assert!(!glob_match("*a*a*a*a*a*a*a*a*a*a*b", &name, true));
+        assert!(glob_match("*a*a*a*a*a*a*a*a*a*a*", &name, true));
+    }
+
+    #[test]
+    fn test_is_glob() {
+        assert!(is_glob("*.pdf"));
+        assert!(is_glob("core.[0-9]"));
+        assert!(!is_glob("Makefile"));
+    }
+}
@@ -0,0 +1,19 @@
+//! Mailcap handling of run-mailcap-rs as a library.
+//!
+//! The mime type of a file is determined with `mimetype::Detector`, the
+//! matching mailcap entries are read with `mailcap::get_entries` (or from a
+//! `cache::MailcapIndex`), and `mailcap::get_final_command` picks the entry
+//! for an action and renders its command.
+
+extern crate libc;
+
+pub mod cache;
+pub mod encoding;
+pub mod glob;
+pub mod lint;
+pub mod mailcap;
+pub mod mimetype;
+pub mod sharedmime;
+pub mod shell;
+pub mod tempfile;
+pub mod terminal;
@@ -0,0 +1,265 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use super::mailcap::*;
+
+#[derive(Debug)]
+#[derive(PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug)]
+pub struct LintMessage {
+    pub severity: Severity,
+    pub path: PathBuf,
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for LintMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+        if self.line == 0 {
+            write!(f, "{}: {}: {}", self.path.display(), severity, self.message)
+        } else {
+            write!(f, "{}:{}: {}: {}", self.path.display(), self.line, severity, self.message)
+        }
+    }
+}
+
+struct QuoteCheck {
+    unbalanced: bool,
+    placeholder_in_nested_shell: bool,
+}
+
+// Substituted values are quoted for the shell running the command, but not
+// for a shell started by it, like the one of backticks or of a quoted
+// "sh -c" script, so placeholders there are unsafe
+fn check_quotes(command: &str) -> QuoteCheck {
+    let mut single_quoted = false;
+    let mut double_quoted = false;
+    let mut backticks = false;
+    let mut nested_script = false;
+    let mut placeholder_in_nested_shell = false;
+    let mut text = String::new();
+    let mut chars = command.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if !single_quoted => {
+                chars.next();
+            },
+            '\'' if !double_quoted => {
+                single_quoted = !single_quoted;
+                nested_script = single_quoted && text.trim_end().ends_with(" -c");
+            },
+            '"' if !single_quoted => {
+                double_quoted = !double_quoted;
+                nested_script = double_quoted && text.trim_end().ends_with(" -c");
+            },
+            '`' if !single_quoted => backticks = !backticks,
+            '%' => {
+                if let Some(next) = chars.next() {
+                    if matches!(next, 's' | 't' | 'F' | '{') && (backticks || nested_script) {
+                        placeholder_in_nested_shell = true;
+                    }
+                }
+            },
+            _ => {},
+        }
+        text.push(c);
+    }
+
+    QuoteCheck {
+        unbalanced: single_quoted || double_quoted,
+        placeholder_in_nested_shell,
+    }
+}
+
+fn commands(entry: &MailcapEntry) -> [(&'static str, &str); 5] {
+    [
+        ("view", &entry.view),
+        ("edit", &entry.edit),
+        ("compose", &entry.compose),
+        ("print", &entry.print),
+        ("test", &entry.test),
+    ]
+}
+
+// Whether the earlier entry handles everything the later one does, given it
+// is tried first
+fn covers(earlier: &MailcapEntry, later: &MailcapEntry) -> bool {
+    if !earlier.test.is_empty() {
+        return false;
+    }
+    let earlier_commands = commands(earlier);
+    let later_commands = commands(later);
+    let all_actions_covered = earlier_commands.iter().zip(later_commands.iter())
+        .take(4)
+        .all(|(earlier, later)| later.1.is_empty() || !earlier.1.is_empty());
+    all_actions_covered && (earlier.copiousoutput || !later.copiousoutput)
+}
+
+fn is_duplicate(earlier: &MailcapEntry, later: &MailcapEntry) -> bool {
+    earlier.mime_type == later.mime_type &&
+        commands(earlier) == commands(later) &&
+        earlier.needsterminal == later.needsterminal &&
+        earlier.copiousoutput == later.copiousoutput
+}
+
+fn lint_entry(path: &Path, entry: &MailcapEntry, earlier_entries: &[(PathBuf, MailcapEntry)], messages: &mut Vec<LintMessage>) {
+    let mut message = |severity, message| {
+        messages.push(LintMessage {
+            severity,
+            path: path.to_path_buf(),
+            line: entry.line,
+            message,
+        });
+    };
+
+    if entry.view.is_empty() {
+        message(Severity::Warning, String::from("Entry has no view command"));
+    }
+
+    for &(name, command) in commands(entry).iter() {
+        let check = check_quotes(command);
+        if check.unbalanced {
+            message(Severity::Error, format!("Unbalanced quotes in {} command", name));
+        }
+        if check.placeholder_in_nested_shell {
+            message(Severity::Warning, format!("Placeholder inside backticks or a \"sh -c\" script in {} command", name));
+        }
+    }
+
+    // Only the entries, that are tried before this one by get_entries, can
+    // hide it
+    let mut entry_types: Vec<&str> = earlier_entries.iter()
+        .map(|(_, earlier)| earlier.mime_type.as_str())
+        .collect();
+    entry_types.push(&entry.mime_type);
+    let tried_before = lookup_order(&entry_types, &[&entry.mime_type]).into_iter()
+        .take_while(|&index| index < earlier_entries.len());
+
+    for index in tried_before {
+        let (ref earlier_path, ref earlier) = earlier_entries[index];
+        if is_duplicate(earlier, entry) {
+            message(Severity::Warning, format!("Duplicate of entry at {}:{}", earlier_path.display(), earlier.line));
+            break;
+        }
+        if covers(earlier, entry) {
+            if is_catch_all(&earlier.mime_type) {
+                message(Severity::Warning, format!("Unreachable after catch-all entry at {}:{}", earlier_path.display(), earlier.line));
+            } else {
+                message(Severity::Warning, format!("Shadowed by entry at {}:{}", earlier_path.display(), earlier.line));
+            }
+            break;
+        }
+    }
+}
+
+pub fn lint(mailcap_paths: &[&Path]) -> Vec<LintMessage> {
+    let mut messages = Vec::new();
+    let mut entries: Vec<(PathBuf, MailcapEntry)> = Vec::new();
+
+    for path in mailcap_paths {
+        let mut warnings = Vec::new();
+        let file_entries = match parse_file(path, ParseMode::Lenient, &mut warnings) {
+            Ok(file_entries) => file_entries,
+            Err(MailcapError { reason: Reason::Io(ref e), line: 0, .. }) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                messages.push(LintMessage {
+                    severity: Severity::Error,
+                    path: e.path,
+                    line: e.line,
+                    message: format!("{}", e.reason),
+                });
+                continue;
+            },
+        };
+
+        for warning in warnings {
+            messages.push(LintMessage {
+                severity: Severity::Error,
+                path: warning.path,
+                line: warning.line,
+                message: format!("{}", warning.reason),
+            });
+        }
+
+        for entry in file_entries {
+            lint_entry(path, &entry, &entries, &mut messages);
+            entries.push((path.to_path_buf(), entry));
+        }
+    }
+
+    messages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_quotes() {
+        assert!(!check_quotes("less '%s'").unbalanced);
+        assert!(!check_quotes("test \"$DISPLAY\" != \"\"").unbalanced);
+        assert!(!check_quotes("echo \"it's\" \\' \\\"").unbalanced);
+        assert!(check_quotes("less '%s").unbalanced);
+        assert!(check_quotes("echo \"foo").unbalanced);
+        assert!(!check_quotes("less '%s'").placeholder_in_nested_shell);
+        assert!(!check_quotes("less \"%s\"").placeholder_in_nested_shell);
+        assert!(!check_quotes("echo '\"%s\"'").placeholder_in_nested_shell);
+        assert!(!check_quotes("sh -c 'less \"$1\"' sh %s").placeholder_in_nested_shell);
+        assert!(check_quotes("sh -c 'less %s'").placeholder_in_nested_shell);
+        assert!(check_quotes("sh -c \"gunzip < '%s' | less\"").placeholder_in_nested_shell);
+        assert!(check_quotes("less `ls %s`").placeholder_in_nested_shell);
+        assert!(!check_quotes("sh -c 'less %%s'").placeholder_in_nested_shell);
+    }
+
+    #[test]
+    fn test_lint() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mailcap-lint");
+        let mut nonexistant_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        nonexistant_path.push("tests/data/mailcap.");
+
+        let mailcap_paths: [&Path; 2] = [path.as_path(), nonexistant_path.as_path()];
+        let messages: Vec<String> = lint(&mailcap_paths).iter()
+            .map(|message| format!("{}", message).replacen(&format!("{}", path.display()), "mailcap-lint", 4))
+            .collect();
+
+        assert_eq!(messages, vec![
+            "mailcap-lint:2: error: Unknown field \"copiusoutput\"",
+            "mailcap-lint:3: error: Unbalanced quotes in view command",
+            "mailcap-lint:4: warning: Placeholder inside backticks or a \"sh -c\" script in view command",
+            "mailcap-lint:5: warning: Entry has no view command",
+            "mailcap-lint:6: warning: Duplicate of entry at mailcap-lint:1",
+            "mailcap-lint:7: warning: Shadowed by entry at mailcap-lint:1",
+            "mailcap-lint:11: warning: Unreachable after catch-all entry at mailcap-lint:9",
+        ]);
+
+        // The lookup tries the entries, that hide others, first, and the
+        // catch-all entries after all others
+        for &(mime_type, hiding_line, hidden_line) in &[("text/plain", 1, 7), ("application/pdf", 10, 9), ("*/*", 9, 11)] {
+            let entries = get_entries(&mailcap_paths, &[mime_type], ParseMode::Lenient, &mut Vec::new()).unwrap();
+            let first = entries.iter().find(|entry| entry.test.is_empty()).unwrap();
+            assert_eq!(first.line, hiding_line);
+            assert!(entries.iter().any(|entry| entry.line == hidden_line));
+        }
+    }
+
+    #[test]
+    fn test_lint_clean() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mailcap");
+
+        let mailcap_paths: [&Path; 1] = [path.as_path()];
+        assert!(lint(&mailcap_paths).is_empty());
+    }
+}
@@ -1,37 +1,174 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::{self, BufReader};
 use std::io::prelude::*;
-use std::process::Command;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use super::config::*;
+use super::shell::{self, Token};
+use super::tempfile::TempDir;
+use super::terminal::{self, Session};
 
 #[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub enum Action {
+    View,
+    Cat,
+    Edit,
+    Compose,
+    Print,
+}
+
+impl Action {
+    pub fn from(actionstr: &str) -> Option<Action> {
+        match actionstr {
+            "view" => Some(Action::View),
+            "see" => Some(Action::View),
+            "cat" => Some(Action::Cat),
+            "edit" => Some(Action::Edit),
+            "change" => Some(Action::Edit),
+            "compose" => Some(Action::Compose),
+            "create" => Some(Action::Compose),
+            "print" => Some(Action::Print),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(Default)]
+pub struct InputFile {
+    pub filename: String,
+    pub mimetype: String,
+    pub mimetype_source: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+// Everything besides the file, that influences the final command
+#[derive(Debug)]
+#[derive(Clone)]
+pub struct CommandOptions {
+    pub action: Action,
+    pub pager: String,
+    pub nopager: bool,
+    pub terminal: String,
+    // Templates for terminals by program name, before the built-in ones
+    pub terminal_templates: Vec<(String, String)>,
+    pub session: Session,
+    // Run commands without a shell, when they do not need one
+    pub noshell: bool,
+}
+
+impl Default for CommandOptions {
+    fn default() -> Self {
+        CommandOptions {
+            action: Action::View,
+            pager: String::from("less"),
+            nopager: false,
+            terminal: String::from("xterm"),
+            terminal_templates: Vec::new(),
+            session: Session::None,
+            noshell: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[derive(Clone, Default)]
+#[derive(PartialEq)]
 pub struct MailcapEntry {
+    pub line: usize,
+    pub mime_type: String,
     pub view: String,
     pub edit: String,
     pub compose: String,
     pub print: String,
     pub test: String,
+    pub nametemplate: String,
     pub needsterminal: bool,
     pub copiousoutput: bool,
 }
 
-impl Default for MailcapEntry {
-    fn default() -> Self {
-        MailcapEntry {
-            view: String::new(),
-            edit: String::new(),
-            compose: String::new(),
-            print: String::new(),
-            test: String::new(),
-            needsterminal: false,
-            copiousoutput: false,
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub enum ParseMode {
+    Lenient,
+    Strict,
+}
+
+#[derive(Debug)]
+pub enum Reason {
+    Io(io::Error),
+    NoMailcapFile,
+    InvalidMimeType(String),
+    MissingViewCommand,
+    MissingValue(String),
+    UnknownField(String),
+}
+
+#[derive(Debug)]
+pub struct MailcapError {
+    pub path: PathBuf,
+    pub line: usize,
+    pub reason: Reason,
+}
+
+impl MailcapError {
+    fn new(path: &Path, line: usize, reason: Reason) -> MailcapError {
+        MailcapError {
+            path: path.to_path_buf(),
+            line,
+            reason,
+        }
+    }
+}
+
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Reason::Io(ref e) => write!(f, "{}", e),
+            Reason::NoMailcapFile => write!(f, "No usable mailcap file found"),
+            Reason::InvalidMimeType(ref mime_type) => write!(f, "Invalid mime type \"{}\"", mime_type),
+            Reason::MissingViewCommand => write!(f, "Missing view command"),
+            Reason::MissingValue(ref key) => write!(f, "Missing value for field \"{}\"", key),
+            Reason::UnknownField(ref key) => write!(f, "Unknown field \"{}\"", key),
+        }
+    }
+}
+
+impl fmt::Display for MailcapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.as_os_str().is_empty() {
+            write!(f, "{}", self.reason)
+        } else if self.line == 0 {
+            write!(f, "{}: {}", self.path.display(), self.reason)
+        } else {
+            write!(f, "{}:{}: {}", self.path.display(), self.line, self.reason)
         }
     }
 }
 
-fn mime_types_match(mailcap_mime_type: &str, mime_type: &str) -> bool {
+impl Error for MailcapError {}
+
+#[derive(Debug)]
+pub struct FinalCommand {
+    pub command: String,
+    // The programs of the command and their arguments, if it can be run
+    // without a shell; more than one are connected by pipes
+    pub pipeline: Option<Vec<Vec<String>>>,
+    // Holds the file created for the nametemplate until the command is done
+    pub tempdir: Option<TempDir>,
+}
+
+pub fn mime_types_match(mailcap_mime_type: &str, mime_type: &str) -> bool {
     let mut mailcap_mime_parts = mailcap_mime_type.split('/');
     let mime_parts = mime_type.split('/');
     let matches;
@@ -42,196 +179,514 @@ fn mime_types_match(mailcap_mime_type: &str, mime_type: &str) -> bool {
     matches && mailcap_mime_parts.count() == 0
 }
 
-fn parse_line(line: &str, mime_type: &str) -> Option<MailcapEntry> {
-    let mut items = line.split(';');
-    if let Some(mime) = items.next() {
-        if mime_types_match(mime, mime_type) {
-            if let Some(command) = items.next() {
-                let mut entry: MailcapEntry = MailcapEntry {
-                    view: String::from(command.trim()),
-                    ..Default::default()
-                };
-                for item in items {
-                    let mut keyvalue = item.splitn(2, '=');
-                    let key = keyvalue.next();
-                    let value = keyvalue.next();
-
-                    match value {
-                        Some(value) => {
-                            match key.unwrap().trim() {
-                                "edit" => entry.edit = String::from(value),
-                                "compose" => entry.compose = String::from(value),
-                                "print" => entry.print = String::from(value),
-                                "test" => entry.test = String::from(value),
-                                _ => continue,
-                            }
-                        }
-                        None => {
-                            match key.unwrap().trim() {
-                                "needsterminal" => entry.needsterminal = true,
-                                "copiousoutput" => entry.copiousoutput = true,
-                                _ => continue,
-                            }
-                        }
-                    }
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => fields.push(std::mem::take(&mut field)),
+            '\\' => match chars.next() {
+                Some(';') => field.push(';'),
+                Some(next) => {
+                    field.push('\\');
+                    field.push(next);
+                },
+                None => field.push('\\'),
+            },
+            _ => field.push(c),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+fn is_continued(line: &str) -> bool {
+    line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+fn parse_line(line: &str, warnings: &mut Vec<Reason>) -> Result<MailcapEntry, Reason> {
+    let fields = split_fields(line);
+    let mut items = fields.iter().map(|field| field.as_str());
+    let mime = items.next().unwrap().trim();
+    if mime.is_empty() || mime.matches('/').count() > 1 || mime.split('/').any(|part| part.is_empty()) {
+        return Err(Reason::InvalidMimeType(mime.to_string()));
+    }
+    let command = match items.next() {
+        Some(command) => command,
+        None => return Err(Reason::MissingViewCommand),
+    };
+    let mut entry: MailcapEntry = MailcapEntry {
+        mime_type: mime.to_string(),
+        view: String::from(command.trim()),
+        ..Default::default()
+    };
+    for item in items {
+        let mut keyvalue = item.splitn(2, '=');
+        let key = keyvalue.next().unwrap().trim();
+        let value = keyvalue.next();
+
+        match value {
+            Some(value) => {
+                match key {
+                    "edit" => entry.edit = String::from(value),
+                    "compose" => entry.compose = String::from(value),
+                    "print" => entry.print = String::from(value),
+                    "test" => entry.test = String::from(value),
+                    "nametemplate" => entry.nametemplate = String::from(value.trim()),
+                    "composetyped" | "description" | "x11-bitmap" | "priority" => continue,
+                    _ if key.starts_with("x-") => continue,
+                    _ => warnings.push(Reason::UnknownField(key.to_string())),
+                }
+            }
+            None => {
+                match key {
+                    "needsterminal" => entry.needsterminal = true,
+                    "copiousoutput" => entry.copiousoutput = true,
+                    "textualnewlines" | "" => continue,
+                    _ if key.starts_with("x-") => continue,
+                    "edit" | "compose" | "composetyped" | "print" | "test" | "nametemplate" |
+                    "description" | "x11-bitmap" | "priority" => warnings.push(Reason::MissingValue(key.to_string())),
+                    _ => warnings.push(Reason::UnknownField(key.to_string())),
                 }
-                return Some(entry);
             }
         }
     }
-    None
+    Ok(entry)
 }
 
-pub fn get_entries(mailcap_paths: &[&Path], mime_type: &str) -> Result<Vec<MailcapEntry>, io::Error> {
-    let mut file_opened = false;
+pub fn parse_file(path: &Path, mode: ParseMode, warnings: &mut Vec<MailcapError>) -> Result<Vec<MailcapEntry>, MailcapError> {
+    let file = File::open(path).map_err(|e| MailcapError::new(path, 0, Reason::Io(e)))?;
+    let file = BufReader::new(file);
     let mut entries = Vec::new();
 
-    for path in mailcap_paths {
-        let file = match File::open(&path) {
-            Ok(file) => file,
-            Err(_e) => continue,
-        };
-        file_opened = true;
-
-        let file = BufReader::new(file);
+    let mut fullline = String::new();
+    let mut firstline = 0;
+    for (index, line) in file.lines().enumerate() {
+        let line = line.map_err(|e| MailcapError::new(path, index + 1, Reason::Io(e)))?;
+        if fullline.is_empty() {
+            firstline = index + 1;
+        }
+        fullline.push_str(&line);
+        if is_continued(&fullline) {
+            fullline.pop();
+            continue;
+        }
+        if fullline.starts_with('#') || fullline.trim().is_empty() {
+            fullline = String::new();
+            continue;
+        }
 
-        let mut fullline = String::new();
-        for line in file.lines() {
-            let line = line?;
-            fullline.push_str(&line);
-            if fullline.ends_with('\\') {
-                fullline.pop();
-                continue;
+        let mut line_warnings = Vec::new();
+        let result = parse_line(&fullline, &mut line_warnings);
+        for reason in line_warnings {
+            let warning = MailcapError::new(path, firstline, reason);
+            if mode == ParseMode::Strict {
+                return Err(warning);
             }
-            if fullline.starts_with('#') {
-                fullline = String::new();
-                continue;
-            }
-            if let Some(entry) = parse_line(&fullline, mime_type) {
+            warnings.push(warning);
+        }
+        match result {
+            Ok(mut entry) => {
+                entry.line = firstline;
                 entries.push(entry);
+            },
+            Err(reason) => {
+                let error = MailcapError::new(path, firstline, reason);
+                if mode == ParseMode::Strict {
+                    return Err(error);
+                }
+                warnings.push(error);
             }
-            fullline = String::new();
+        }
+        fullline = String::new();
+    }
+    Ok(entries)
+}
+
+pub fn is_catch_all(mime_type: &str) -> bool {
+    mime_type == "*" || mime_type == "*/*"
+}
+
+pub fn get_entries(mailcap_paths: &[&Path], mime_types: &[&str], mode: ParseMode, warnings: &mut Vec<MailcapError>) -> Result<Vec<MailcapEntry>, MailcapError> {
+    let mut file_opened = false;
+    let mut all_entries = Vec::new();
+
+    for path in mailcap_paths {
+        match parse_file(path, mode, warnings) {
+            Ok(file_entries) => {
+                file_opened = true;
+                all_entries.extend(file_entries.into_iter().map(Some));
+            },
+            Err(MailcapError { reason: Reason::Io(ref e), line: 0, .. }) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(error) => {
+                if mode == ParseMode::Strict {
+                    return Err(error);
+                }
+                warnings.push(error);
+            },
         }
     }
 
     if !file_opened {
-        return Err(io::Error::new(io::ErrorKind::NotFound, "No usable mailcap file found"));
+        return Err(MailcapError::new(Path::new(""), 0, Reason::NoMailcapFile));
     }
-    Ok(entries)
+
+    let entry_types: Vec<&str> = all_entries.iter()
+        .map(|entry| entry.as_ref().unwrap().mime_type.as_str())
+        .collect();
+    let order = lookup_order(&entry_types, mime_types);
+    Ok(order.into_iter().map(|index| all_entries[index].take().unwrap()).collect())
+}
+
+// The positions of the entries, that are tried for a mime type and its
+// parent types, in the order they are tried: the entries matching the type
+// itself in file order ("major/*" included), then those for the parent
+// types, and the catch-all entries last, so they do not hide the entries
+// for the parent types
+pub fn lookup_order(entry_types: &[&str], mime_types: &[&str]) -> Vec<usize> {
+    let mut taken = vec![false; entry_types.len()];
+    let mut order = Vec::new();
+    for mime_type in mime_types {
+        for (index, entry_type) in entry_types.iter().enumerate() {
+            if !taken[index] && !is_catch_all(entry_type) && mime_types_match(entry_type, mime_type) {
+                taken[index] = true;
+                order.push(index);
+            }
+        }
+    }
+    order.extend(entry_types.iter().enumerate()
+        .filter(|&(_, entry_type)| is_catch_all(entry_type))
+        .filter(|&(_, entry_type)| mime_types.iter().any(|mime_type| mime_types_match(entry_type, mime_type)))
+        .map(|(index, _)| index));
+    order
 }
 
-fn command_replace_placeholder(string: &str, config: &Config) -> String {
+pub fn render_command(string: &str, file: &InputFile, parts: &[InputFile]) -> String {
+    shell::join(&tokenize_command(string, file, parts))
+}
+
+// Replaces the placeholders, but keeps the values apart from the command text
+fn tokenize_command(string: &str, file: &InputFile, parts: &[InputFile]) -> Vec<Token> {
     enum ReplaceState {
         Character,
         PerCent,
+        Parameter,
         Escape,
     }
 
     let mut state = ReplaceState::Character;
-    let mut single_quote_flag = false;
-    let mut newstring = String::new();
+    let mut tokens = Vec::new();
+    let mut parameter = String::new();
 
     for c in string.chars() {
         match state {
             ReplaceState::Character => match c {
                 '%' => state = ReplaceState::PerCent,
                 '\\' => state = ReplaceState::Escape,
-                '\'' => {
-                    single_quote_flag = !single_quote_flag;
-                    newstring.push(c)
-                },
-                _ => newstring.push(c),
+                _ => tokens.push(Token::Char(c)),
             }
             ReplaceState::PerCent => match c {
                 's' => {
-                    for fc in config.filename.chars() {
-                        if fc == '\'' {
-                            if single_quote_flag {
-                                newstring.push_str("'\\''");
-                            } else {
-                                newstring.push_str("\\'");
-                            }
-                        } else {
-                            newstring.push(fc);
-                        }
-                    }
+                    tokens.push(Token::Value(file.filename.clone()));
                     state = ReplaceState::Character;
                 }
                 't' => {
-                    newstring.push_str(&config.mimetype);
+                    tokens.push(Token::Value(file.mimetype.clone()));
                     state = ReplaceState::Character;
                 }
-                '%' => newstring.push('%'),
+                'n' => {
+                    tokens.push(Token::Value(parts.len().to_string()));
+                    state = ReplaceState::Character;
+                }
+                'F' => {
+                    let values = parts.iter()
+                        .flat_map(|part| vec![part.mimetype.clone(), part.filename.clone()])
+                        .collect();
+                    tokens.push(Token::Values(values));
+                    state = ReplaceState::Character;
+                }
+                '{' => state = ReplaceState::Parameter,
+                '%' => tokens.push(Token::Char('%')),
                 _ => {
-                    newstring.push('%');
-                    newstring.push(c);
+                    tokens.push(Token::Char('%'));
+                    tokens.push(Token::Char(c));
                     state = ReplaceState::Character;
                 }
             }
+            ReplaceState::Parameter => match c {
+                '}' => {
+                    let value = file.parameters.iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(&parameter))
+                        .map_or("", |(_, value)| value.as_str());
+                    tokens.push(Token::QuotedValue(value.to_string()));
+                    parameter.clear();
+                    state = ReplaceState::Character;
+                }
+                _ => parameter.push(c),
+            }
             ReplaceState::Escape => match c {
                 '%' => {
-                    newstring.push('%');
+                    // An escaped percent sign is literal text for the shell
+                    // as well, so it must not be unescaped by it again
+                    tokens.push(Token::Value(String::from("%")));
                     state = ReplaceState::Character;
                 }
-                '\\' => newstring.push('\\'),
+                '\\' => tokens.push(Token::Char('\\')),
                 _ => {
-                    newstring.push('\\');
-                    newstring.push(c);
+                    tokens.push(Token::Char('\\'));
+                    tokens.push(Token::Char(c));
                     state = ReplaceState::Character;
                 }
             }
         }
     }
-    newstring
+    if let ReplaceState::Parameter = state {
+        tokens.extend("%{".chars().chain(parameter.chars()).map(Token::Char));
+    }
+    tokens
+}
+
+#[derive(Debug)]
+pub enum TestOutcome {
+    Exited(ExitStatus),
+    TimedOut,
+    Error(io::Error),
+    // The same command was already run before
+    Cached(bool),
+}
+
+impl fmt::Display for TestOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TestOutcome::Exited(ref status) => write!(f, "{}", status),
+            TestOutcome::TimedOut => write!(f, "timed out"),
+            TestOutcome::Error(ref e) => write!(f, "could not be run: {}", e),
+            TestOutcome::Cached(true) => write!(f, "reused earlier result: passed"),
+            TestOutcome::Cached(false) => write!(f, "reused earlier result: failed"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResult {
+    pub command: String,
+    pub outcome: TestOutcome,
+    pub duration: Duration,
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        match self.outcome {
+            TestOutcome::Exited(ref status) => status.success(),
+            TestOutcome::Cached(passed) => passed,
+            _ => false,
+        }
+    }
+}
+
+// Runs the test commands of mailcap entries and keeps their results. Each
+// command is only run once, unless it contains the file name and
+// memoize_file_tests is false, because the file may change in between.
+#[derive(Debug)]
+#[derive(Default)]
+pub struct Tester {
+    pub timeout: Option<Duration>,
+    pub memoize_file_tests: bool,
+    pub results: Vec<TestResult>,
+    passed: HashMap<String, bool>,
+}
+
+fn wait_with_timeout(child: &mut Child, timeout: Option<Duration>) -> TestOutcome {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return child.wait().map_or_else(TestOutcome::Error, TestOutcome::Exited),
+    };
+
+    let start = Instant::now();
+    let mut interval = Duration::from_millis(1);
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return TestOutcome::Exited(status),
+            Ok(None) => {},
+            Err(e) => return TestOutcome::Error(e),
+        }
+        let elapsed = start.elapsed();
+        if elapsed >= timeout {
+            break;
+        }
+        thread::sleep(interval.min(timeout - elapsed));
+        interval = (interval * 2).min(Duration::from_millis(50));
+    }
+
+    // The test runs in its own process group, so this also gets everything
+    // it has started
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+    TestOutcome::TimedOut
+}
+
+impl Tester {
+    pub fn new(timeout: Option<Duration>) -> Tester {
+        Tester {
+            timeout,
+            memoize_file_tests: true,
+            ..Default::default()
+        }
+    }
+
+    fn run(&mut self, template: &str, command: &str, argv: Option<Vec<String>>) -> bool {
+        let memoize = self.memoize_file_tests || !template.contains("%s");
+        if memoize {
+            if let Some(&passed) = self.passed.get(command) {
+                self.results.push(TestResult {
+                    command: command.to_string(),
+                    outcome: TestOutcome::Cached(passed),
+                    duration: Duration::default(),
+                });
+                return passed;
+            }
+        }
+
+        let mut process = match argv {
+            Some(argv) => {
+                let mut process = Command::new(&argv[0]);
+                process.args(&argv[1..]).stdout(Stdio::null());
+                process
+            },
+            None => {
+                let mut process = Command::new("sh");
+                process.arg("-c").arg(format!("{} 2>&1 > /dev/null", command));
+                process
+            },
+        };
+        process.stdin(Stdio::null()).process_group(0);
+
+        let start = Instant::now();
+        let outcome = match process.spawn() {
+            Ok(mut child) => wait_with_timeout(&mut child, self.timeout),
+            Err(e) => TestOutcome::Error(e),
+        };
+        let result = TestResult {
+            command: command.to_string(),
+            outcome,
+            duration: start.elapsed(),
+        };
+        let passed = result.passed();
+        self.results.push(result);
+        if memoize {
+            self.passed.insert(command.to_string(), passed);
+        }
+        passed
+    }
 }
 
-pub fn get_final_command<'a, I>(config: &Config, isatty: bool, mailcap_entries: I) -> Option<String>
+fn apply_nametemplate(nametemplate: &str, filename: &str) -> Option<String> {
+    if nametemplate.is_empty() || nametemplate.contains('/') {
+        return None;
+    }
+
+    let basename = filename.rsplit('/').next().unwrap();
+    let mut template_parts = nametemplate.splitn(2, "%s");
+    let prefix = template_parts.next().unwrap();
+    match template_parts.next() {
+        Some(suffix) => {
+            if basename.len() >= prefix.len() + suffix.len() && basename.starts_with(prefix) && basename.ends_with(suffix) {
+                None
+            } else {
+                Some(format!("{}{}{}", prefix, basename, suffix))
+            }
+        },
+        None => {
+            if basename == nametemplate {
+                None
+            } else {
+                Some(nametemplate.to_string())
+            }
+        },
+    }
+}
+
+fn link_to_nametemplate(file: &InputFile, name: &str, action: Action) -> Result<(InputFile, TempDir), io::Error> {
+    let tempdir = TempDir::new()?;
+    // Changes to a copy would be lost with the temporary directory
+    let copy = !matches!(action, Action::Edit | Action::Compose);
+    let path = tempdir.link_file(name, Path::new(&file.filename), copy)?;
+    let file = InputFile {
+        filename: path.to_string_lossy().into_owned(),
+        ..file.clone()
+    };
+    Ok((file, tempdir))
+}
+
+pub fn get_final_command<'a, I>(options: &CommandOptions, tester: &mut Tester, file: &InputFile, parts: &[InputFile], isatty: bool, mailcap_entries: I) -> Option<FinalCommand>
 where
     I: IntoIterator<Item = &'a MailcapEntry>,
 {
     for entry in mailcap_entries {
-        let command = match config.action {
+        let command = match options.action {
             Action::View => &entry.view,
             Action::Cat => &entry.view,
             Action::Edit => &entry.edit,
             Action::Compose => &entry.compose,
             Action::Print => &entry.print,
         };
-        if command != "" {
-            if config.action == Action::Cat && !entry.copiousoutput {
+        if !command.is_empty() {
+            if options.action == Action::Cat && !entry.copiousoutput {
                 continue;
             }
 
-            let mut command = command_replace_placeholder(command, &config);
+            // If the file can not be linked, e.g. because it is yet to be
+            // composed, the command gets the file name as it is
+            let mut tempdir = None;
+            let mut linked_file = None;
+            if let Some(name) = apply_nametemplate(&entry.nametemplate, &file.filename) {
+                if let Ok((file, dir)) = link_to_nametemplate(file, &name, options.action) {
+                    linked_file = Some(file);
+                    tempdir = Some(dir);
+                }
+            }
+            let file = linked_file.as_ref().unwrap_or(file);
 
-            if entry.test != "" {
-                let testcommand = command_replace_placeholder(&entry.test, &config) + " 2>&1 > /dev/null";
-                if let Ok(status) = Command::new("sh")
-                    .arg("-c")
-                    .arg(testcommand)
-                    .status() {
-                    if !status.success() {
-                            continue;
-                    }
+            let mut pipeline = if options.noshell {
+                shell::split_words(&tokenize_command(command, file, parts)).map(|argv| vec![argv])
+            } else {
+                None
+            };
+            let mut command = render_command(command, file, parts);
+
+            if !entry.test.is_empty() {
+                let testcommand = render_command(&entry.test, file, parts);
+                let argv = if options.noshell {
+                    shell::split_words(&tokenize_command(&entry.test, file, parts))
                 } else {
+                    None
+                };
+                if !tester.run(&entry.test, &testcommand, argv) {
                     continue;
                 }
             }
 
-            if entry.copiousoutput && !config.nopager && config.action == Action::View {
-                command = command + "|" + &config.pager;
+            if entry.copiousoutput && !options.nopager && options.action == Action::View {
+                command = command + "|" + &options.pager;
+                pipeline = pipeline.and_then(|mut pipeline| {
+                    pipeline.push(shell::split(&options.pager)?);
+                    Some(pipeline)
+                });
             }
 
-            if entry.needsterminal && config.action != Action::Print {
+            if entry.needsterminal && options.action != Action::Print {
                 if isatty {
-                    return Some(command);
-                } else if config.running_in_x {
-                    return Some(format!("{} -T \"{}\" -e sh -c \"{}\"", config.xtermcmd, command, command));
+                    return Some(FinalCommand { command, pipeline, tempdir });
                 } else {
-                    return None
+                    let launch = terminal::launch(options.session, &options.terminal, &options.terminal_templates, &command, pipeline.is_some(), &mut tempdir)?;
+                    return Some(FinalCommand { command: launch.command, pipeline: launch.pipeline, tempdir });
                 }
             } else {
-                return Some(command);
+                return Some(FinalCommand { command, pipeline, tempdir });
             }
         }
     }
@@ -248,9 +703,13 @@ mod tests {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push("tests/data/mailcap.");
 
-        let mime_paths: [&Path; 1] = [&path.as_path()];
-        let results = get_entries(&mime_paths, "text/plain").unwrap_err();
-        assert_eq!(results.kind(), io::ErrorKind::NotFound);
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let mut warnings = Vec::new();
+        let results = get_entries(&mime_paths, &["text/plain"], ParseMode::Lenient, &mut warnings).unwrap_err();
+        match results.reason {
+            Reason::NoMailcapFile => {},
+            _ => panic!("unexpected error: {}", results),
+        }
     }
 
     #[test]
@@ -258,8 +717,8 @@ mod tests {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push("tests/data/mailcap");
 
-        let mime_paths: [&Path; 1] = [&path.as_path()];
-        let results = get_entries(&mime_paths, "text/foo").unwrap();
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let results = get_entries(&mime_paths, &["text/foo"], ParseMode::Lenient, &mut Vec::new()).unwrap();
         assert_eq!(results.len(), 0);
     }
 
@@ -268,13 +727,13 @@ mod tests {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push("tests/data/mailcap");
 
-        let mime_paths: [&Path; 1] = [&path.as_path()];
-        let results = get_entries(&mime_paths, "text/plain").unwrap();
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let results = get_entries(&mime_paths, &["text/plain"], ParseMode::Lenient, &mut Vec::new()).unwrap();
         assert_eq!(results.len(), 1);
         assert_eq!(results[0].view, "less '%s'");
         assert_eq!(results[0].edit, "vi '%s'");
         assert_eq!(results[0].test, "test \"$DISPLAY\" != \"\"");
-        assert_eq!(results[0].needsterminal, true);
+        assert!(results[0].needsterminal);
     }
 
     #[test]
@@ -282,30 +741,162 @@ mod tests {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push("tests/data/mailcap-wildcard");
 
-        let mime_paths: [&Path; 1] = [&path.as_path()];
-        let results = get_entries(&mime_paths, "text/plain").unwrap();
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let results = get_entries(&mime_paths, &["text/plain"], ParseMode::Lenient, &mut Vec::new()).unwrap();
         assert_eq!(results.len(), 3);
         assert_eq!(results[0].view, "less '%s'");
         assert_eq!(results[0].edit, "vi '%s'");
         assert_eq!(results[0].test, "test \"$DISPLAY\" != \"\"");
-        assert_eq!(results[0].needsterminal, true);
+        assert!(results[0].needsterminal);
         assert_eq!(results[1].view, "cat '%s'");
         assert_eq!(results[2].view, "hexdump '%s'");
 
-        let results = get_entries(&mime_paths, "video/x-matroska").unwrap();
+        let results = get_entries(&mime_paths, &["video/x-matroska"], ParseMode::Lenient, &mut Vec::new()).unwrap();
         assert_eq!(results.len(), 3);
         assert_eq!(results[0].view, "mpv '%s'");
         assert_eq!(results[1].view, "mplayer '%s'");
         assert_eq!(results[2].view, "hexdump '%s'");
     }
 
+    #[test]
+    fn test_mailcap_invalid_entries() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mailcap-invalid");
+
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let mut warnings = Vec::new();
+        let results = get_entries(&mime_paths, &["text/plain"], ParseMode::Lenient, &mut warnings).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].view, "less '%s'");
+        assert_eq!(results[0].line, 1);
+        assert_eq!(results[1].view, "cat '%s'");
+        assert_eq!(results[1].line, 4);
+
+        let warnings: Vec<String> = warnings.iter().map(|warning| format!("{}:{}", warning.line, warning.reason)).collect();
+        assert_eq!(warnings, vec![
+            "1:Unknown field \"edti\"",
+            "5:Missing view command",
+            "6:Invalid mime type \"text/plain/foo\"",
+            "7:Invalid mime type \"\"",
+            "8:Missing value for field \"test\"",
+            "8:Unknown field \"copiusoutput\"",
+        ]);
+    }
+
+    #[test]
+    fn test_mailcap_invalid_entries_strict() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mailcap-invalid");
+
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let mut warnings = Vec::new();
+        let error = get_entries(&mime_paths, &["text/plain"], ParseMode::Strict, &mut warnings).unwrap_err();
+        assert_eq!(error.path, path);
+        assert_eq!(error.line, 1);
+        assert_eq!(format!("{}", error), format!("{}:1: Unknown field \"edti\"", path.display()));
+        assert_eq!(warnings.len(), 0);
+    }
+
+    #[test]
+    fn test_split_fields() {
+        assert_eq!(split_fields("text/plain; less '%s'"), vec!["text/plain", " less '%s'"]);
+        assert_eq!(split_fields("text/plain; sh -c 'a\\; b'; needsterminal"), vec!["text/plain", " sh -c 'a; b'", " needsterminal"]);
+        assert_eq!(split_fields("text/plain; echo \"a\\;b\""), vec!["text/plain", " echo \"a;b\""]);
+        assert_eq!(split_fields("text/plain; echo a\\\\; copiousoutput"), vec!["text/plain", " echo a\\\\", " copiousoutput"]);
+        assert_eq!(split_fields("text/plain; cat '\\%s' \\n"), vec!["text/plain", " cat '\\%s' \\n"]);
+        assert_eq!(split_fields("text/plain; cat %s\\"), vec!["text/plain", " cat %s\\"]);
+        assert_eq!(split_fields("text/plain"), vec!["text/plain"]);
+        assert_eq!(split_fields("text/plain;"), vec!["text/plain", ""]);
+    }
+
+    #[test]
+    fn test_is_continued() {
+        assert!(is_continued("text/plain; less %s; \\"));
+        assert!(!is_continued("text/plain; less %s; \\\\"));
+        assert!(is_continued("text/plain; less %s; \\\\\\"));
+        assert!(!is_continued("text/plain; less %s;"));
+    }
+
+    #[test]
+    fn test_mailcap_escaped_semicolons() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mailcap-debian");
+
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let mut warnings = Vec::new();
+
+        let results = get_entries(&mime_paths, &["text/html"], ParseMode::Strict, &mut warnings).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].view, "/usr/bin/sensible-browser %s");
+        assert_eq!(results[0].nametemplate, "%s.html");
+        assert_eq!(results[1].view, "/usr/bin/w3m -T text/html %s");
+        assert!(results[1].needsterminal);
+
+        let results = get_entries(&mime_paths, &["application/x-tar"], ParseMode::Strict, &mut warnings).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].view, "sh -c 'tar tvf %s; echo'");
+        assert_eq!(results[0].print, "sh -c 'tar tvf %s | lpr; echo printed'");
+        assert!(results[0].copiousoutput);
+
+        let results = get_entries(&mime_paths, &["application/pdf"], ParseMode::Strict, &mut warnings).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].view, "/usr/bin/evince %s");
+        assert_eq!(results[0].test, "test -n \"$DISPLAY\" -o -n \"$WAYLAND_DISPLAY\"");
+
+        let results = get_entries(&mime_paths, &["text/troff"], ParseMode::Strict, &mut warnings).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].view, "/usr/bin/nroff -mandoc -Tutf8 %s");
+        assert_eq!(results[0].print, "/usr/bin/nroff -mandoc -Tutf8 %s | lpr");
+
+        let results = get_entries(&mime_paths, &["text/x-shellscript"], ParseMode::Strict, &mut warnings).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].view, "cat '%s'; echo \"\\\\\"");
+        assert_eq!(results[0].edit, "vi %s");
+
+        assert_eq!(warnings.len(), 0);
+    }
+
+    #[test]
+    fn test_mailcap_type_hierarchy() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mailcap-wildcard");
+
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let mime_types = ["application/xhtml+xml", "text/html", "text/plain", "application/octet-stream"];
+        let results = get_entries(&mime_paths, &mime_types, ParseMode::Lenient, &mut Vec::new()).unwrap();
+        // The catch-all entry comes after the entries for the parent types
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].view, "cat '%s'");
+        assert_eq!(results[1].view, "less '%s'");
+        assert_eq!(results[2].view, "hexdump '%s'");
+
+        let mime_types = ["video/x-matroska", "application/octet-stream"];
+        let results = get_entries(&mime_paths, &mime_types, ParseMode::Lenient, &mut Vec::new()).unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].view, "mpv '%s'");
+        assert_eq!(results[1].view, "mplayer '%s'");
+        assert_eq!(results[2].view, "hexdump '%s'");
+    }
+
+    #[test]
+    fn test_mailcap_catchall_first() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mailcap-catchall");
+
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let results = get_entries(&mime_paths, &["text/plain"], ParseMode::Strict, &mut Vec::new()).unwrap();
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].view, "less '%s'");
+        assert_eq!(results[1].view, "hexdump -C '%s'");
+    }
+
     #[test]
     fn test_mailcap_ignorecomments() {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push("tests/data/mailcap");
 
-        let mime_paths: [&Path; 1] = [&path.as_path()];
-        let results = get_entries(&mime_paths, "#text/plain").unwrap();
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let results = get_entries(&mime_paths, &["#text/plain"], ParseMode::Lenient, &mut Vec::new()).unwrap();
         assert_eq!(results.len(), 0);
     }
 
@@ -313,124 +904,127 @@ mod tests {
     fn test_final_command() {
         let entries: [MailcapEntry; 2] = [
             MailcapEntry{
+                line: 0,
+                mime_type: String::from("text/plain"),
                 view: String::from("cat '%s'"),
                 edit: String::new(),
                 compose: String::new(),
                 print: String::new(),
                 test: String::new(),
+                nametemplate: String::new(),
                 copiousoutput: false,
                 needsterminal: true,
             },
             MailcapEntry{
+                line: 0,
+                mime_type: String::from("text/plain"),
                 view: String::new(),
                 edit: String::from("vim '%s'"),
                 compose: String::new(),
                 print: String::from("lpr '%s'"),
                 test: String::new(),
+                nametemplate: String::new(),
                 copiousoutput: false,
                 needsterminal: true,
             },
         ];
 
-        let config = Config {
+        let file = InputFile {
             filename: String::from("test.txt"),
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "cat 'test.txt'");
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "cat 'test.txt'");
+
+        let options = CommandOptions {
             action: Action::Edit,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "vim 'test.txt'");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "vim 'test.txt'");
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions {
             action: Action::Compose,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries), None);
+        assert!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).is_none());
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions {
             action: Action::Edit,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, false, &entries), None);
+        assert!(get_final_command(&options, &mut Tester::default(), &file, &[], false, &entries).is_none());
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions {
             action: Action::Edit,
-            running_in_x: true,
+            session: Session::X11,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, false, &entries).unwrap(), "xterm -T \"vim 'test.txt'\" -e sh -c \"vim 'test.txt'\"");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], false, &entries).unwrap().command, "xterm -T 'vim '\\''test.txt'\\''' -e sh -c 'vim '\\''test.txt'\\'''");
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions {
             action: Action::Edit,
-            xtermcmd: String::from("urxvt"),
-            running_in_x: true,
+            terminal: String::from("urxvt"),
+            session: Session::X11,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, false, &entries).unwrap(), "urxvt -T \"vim 'test.txt'\" -e sh -c \"vim 'test.txt'\"");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], false, &entries).unwrap().command, "urxvt -T 'vim '\\''test.txt'\\''' -e sh -c 'vim '\\''test.txt'\\'''");
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions {
             action: Action::Print,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, false, &entries).unwrap(), "lpr 'test.txt'");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], false, &entries).unwrap().command, "lpr 'test.txt'");
     }
 
     #[test]
     fn test_final_command_copiousoutput() {
         let entries: [MailcapEntry; 1] = [
             MailcapEntry{
+                line: 0,
+                mime_type: String::from("text/plain"),
                 view: String::from("cat '%s'"),
                 edit: String::from("vim '%s'"),
                 compose: String::new(),
                 print: String::from("lpr '%s'"),
                 test: String::new(),
+                nametemplate: String::new(),
                 copiousoutput: true,
                 needsterminal: true,
             },
         ];
 
-        let config = Config {
+        let file = InputFile {
             filename: String::from("test.txt"),
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "cat 'test.txt'|less");
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "cat 'test.txt'|less");
+
+        let options = CommandOptions {
             action: Action::Edit,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "vim 'test.txt'");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "vim 'test.txt'");
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions {
             nopager: true,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "cat 'test.txt'");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "cat 'test.txt'");
 
-        let config = Config {
-            filename: String::from("test.txt"),
-            running_in_x: true,
+        let options = CommandOptions {
+            session: Session::X11,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, false, &entries).unwrap(), "xterm -T \"cat 'test.txt'|less\" -e sh -c \"cat 'test.txt'|less\"");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], false, &entries).unwrap().command, "xterm -T 'cat '\\''test.txt'\\''|less' -e sh -c 'cat '\\''test.txt'\\''|less'");
 
-        let config = Config {
-            filename: String::from("test.txt"),
+        let options = CommandOptions {
             action: Action::Print,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, false, &entries).unwrap(), "lpr 'test.txt'");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], false, &entries).unwrap().command, "lpr 'test.txt'");
     }
 
     #[test]
@@ -447,12 +1041,16 @@ mod tests {
             },
         ];
 
-        let config = Config {
+        let file = InputFile {
             filename: String::from("bar.txt"),
+            ..Default::default()
+        };
+
+        let options = CommandOptions {
             action: Action::Cat,
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "cat 'bar.txt'");
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "cat 'bar.txt'");
     }
 
     #[test]
@@ -470,11 +1068,13 @@ mod tests {
             },
         ];
 
-        let config = Config {
+        let file = InputFile {
             filename: String::from("bar.txt"),
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "less 'bar.txt'");
+
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "less 'bar.txt'");
     }
 
     #[test]
@@ -486,11 +1086,13 @@ mod tests {
             },
         ];
 
-        let config = Config {
+        let file = InputFile {
             filename: String::from("test.txt"),
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "cat '\\%s' %test.txt");
+
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "cat '\\%s' %test.txt");
     }
 
     #[test]
@@ -502,12 +1104,14 @@ mod tests {
             },
         ];
 
-        let config = Config {
+        let file = InputFile {
             filename: String::from("test.txt"),
             mimetype: String::from("application/pdf"),
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "echo application/pdf test.txt");
+
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "echo application/pdf test.txt");
     }
 
     #[test]
@@ -519,11 +1123,13 @@ mod tests {
             },
         ];
 
-        let config = Config {
+        let file = InputFile {
             filename: String::from("fo'o.txt"),
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "cat 'fo'\\''o.txt'");
+
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "cat 'fo'\\''o.txt'");
     }
 
     #[test]
@@ -535,11 +1141,433 @@ mod tests {
             },
         ];
 
-        let config = Config {
+        let file = InputFile {
             filename: String::from("fo'o.txt"),
             ..Default::default()
         };
-        assert_eq!(get_final_command(&config, true, &entries).unwrap(), "cat fo\\'o.txt");
+
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "cat fo\\'o.txt");
+    }
+
+    #[test]
+    fn test_mailcap_nametemplate() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mailcap");
+
+        let mime_paths: [&Path; 1] = [path.as_path()];
+        let results = get_entries(&mime_paths, &["text/html"], ParseMode::Lenient, &mut Vec::new()).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].nametemplate, "%s.html");
+    }
+
+    #[test]
+    fn test_apply_nametemplate() {
+        assert_eq!(apply_nametemplate("", "foo"), None);
+        assert_eq!(apply_nametemplate("%s.html", "foo.html"), None);
+        assert_eq!(apply_nametemplate("%s.html", "dir/foo.html"), None);
+        assert_eq!(apply_nametemplate("%s.html", "foo"), Some(String::from("foo.html")));
+        assert_eq!(apply_nametemplate("%s.html", "dir.html/foo"), Some(String::from("foo.html")));
+        assert_eq!(apply_nametemplate("x-%s.html", "foo.html"), Some(String::from("x-foo.html.html")));
+        assert_eq!(apply_nametemplate("index.html", "foo"), Some(String::from("index.html")));
+        assert_eq!(apply_nametemplate("../%s.html", "foo"), None);
+    }
+
+    #[test]
+    fn test_final_command_nametemplate() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mime.types");
+
+        let entries: [MailcapEntry; 1] = [
+            MailcapEntry{
+                view: String::from("cat %s"),
+                test: String::from("test -f %s"),
+                nametemplate: String::from("%s.txt"),
+                ..Default::default()
+            },
+        ];
+
+        let file = InputFile {
+            filename: path.to_string_lossy().into_owned(),
+            ..Default::default()
+        };
+
+        let options = CommandOptions::default();
+        let final_command = get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap();
+        let linked_path = PathBuf::from(final_command.command.trim_start_matches("cat "));
+        assert_eq!(linked_path.file_name().unwrap(), "mime.types.txt");
+        assert_eq!(std::fs::read(&linked_path).unwrap(), std::fs::read(&path).unwrap());
+
+        drop(final_command);
+        assert!(!linked_path.exists());
+    }
+
+    #[test]
+    fn test_final_command_nametemplate_missing_file() {
+        let entries: [MailcapEntry; 1] = [
+            MailcapEntry{
+                view: String::from("firefox %s"),
+                compose: String::from("vi %s"),
+                nametemplate: String::from("%s.html"),
+                ..Default::default()
+            },
+        ];
+
+        let file = InputFile {
+            filename: String::from("nonexistant/newdoc"),
+            ..Default::default()
+        };
+
+        let options = CommandOptions {
+            action: Action::Compose,
+            ..Default::default()
+        };
+        let final_command = get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap();
+        assert_eq!(final_command.command, "vi nonexistant/newdoc");
+        assert!(final_command.tempdir.is_none());
+    }
+
+    #[test]
+    fn test_final_command_insert_parameter() {
+        let entries: [MailcapEntry; 1] = [
+            MailcapEntry{
+                view: String::from("iconv -f %{charset} %s; echo '%{Charset}' %{format} %{foo"),
+                test: String::from("test %{charset} = iso-8859-1"),
+                ..Default::default()
+            },
+        ];
+
+        let file = InputFile {
+            filename: String::from("test.txt"),
+            mimetype: String::from("text/plain"),
+            parameters: vec![
+                (String::from("charset"), String::from("iso-8859-1")),
+                (String::from("format"), String::from("it's flowed")),
+            ],
+            ..Default::default()
+        };
+
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "iconv -f 'iso-8859-1' test.txt; echo 'iso-8859-1' 'it'\\''s flowed' %{foo");
+    }
+
+    #[test]
+    fn test_final_command_missing_parameter() {
+        let entries: [MailcapEntry; 1] = [
+            MailcapEntry{
+                view: String::from("cat %s %{charset}"),
+                ..Default::default()
+            },
+        ];
+
+        let file = InputFile {
+            filename: String::from("test.txt"),
+            ..Default::default()
+        };
+
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "cat test.txt ''");
+    }
+
+    #[test]
+    fn test_final_command_multipart() {
+        let entries: [MailcapEntry; 1] = [
+            MailcapEntry{
+                view: String::from("showmulti %n %F"),
+                ..Default::default()
+            },
+        ];
+
+        let file = InputFile {
+            filename: String::from("message"),
+            mimetype: String::from("multipart/mixed"),
+            ..Default::default()
+        };
+
+        let parts = vec![
+                InputFile {
+                    filename: String::from("part1.txt"),
+                    mimetype: String::from("text/plain"),
+                    ..Default::default()
+                },
+                InputFile {
+                    filename: String::from("it's.png"),
+                    mimetype: String::from("image/png"),
+                    ..Default::default()
+                },
+        ];
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &parts, true, &entries).unwrap().command, "showmulti 2 'text/plain' 'part1.txt' 'image/png' 'it'\\''s.png'");
+
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().command, "showmulti 0 ");
+    }
+
+    #[test]
+    fn test_final_command_noshell() {
+        let entries: [MailcapEntry; 3] = [
+            MailcapEntry{
+                mime_type: String::from("text/plain"),
+                view: String::from("cat %s"),
+                test: String::from("test -n '%s'"),
+                copiousoutput: true,
+                ..Default::default()
+            },
+            MailcapEntry{
+                mime_type: String::from("text/plain"),
+                view: String::from("vim -- \"%s\""),
+                edit: String::from("sed -n p %s | vim -"),
+                needsterminal: true,
+                ..Default::default()
+            },
+            MailcapEntry{
+                mime_type: String::from("text/plain"),
+                view: String::from("cat %s"),
+                print: String::from("lpr -T %{title} %s"),
+                ..Default::default()
+            },
+        ];
+
+        let file = InputFile {
+            filename: String::from("$(false) a'b.txt"),
+            parameters: vec![(String::from("title"), String::from("it's"))],
+            ..Default::default()
+        };
+        let argv = |words: &[&str]| words.iter().map(|word| word.to_string()).collect::<Vec<String>>();
+
+        let options = CommandOptions {
+            noshell: true,
+            ..Default::default()
+        };
+        let final_command = get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap();
+        assert_eq!(final_command.pipeline, Some(vec![argv(&["cat", "$(false) a'b.txt"]), argv(&["less"])]));
+
+        let options = CommandOptions {
+            action: Action::View,
+            nopager: true,
+            noshell: true,
+            pager: String::from("less | cat"),
+            ..Default::default()
+        };
+        let final_command = get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap();
+        assert_eq!(final_command.pipeline, Some(vec![argv(&["cat", "$(false) a'b.txt"])]));
+
+        let options = CommandOptions {
+            pager: String::from("less | cat"),
+            noshell: true,
+            ..Default::default()
+        };
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().pipeline, None);
+
+        let options = CommandOptions {
+            action: Action::Edit,
+            noshell: true,
+            ..Default::default()
+        };
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().pipeline, None);
+
+        let options = CommandOptions {
+            action: Action::Print,
+            noshell: true,
+            ..Default::default()
+        };
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().pipeline, Some(vec![argv(&["lpr", "-T", "it's", "$(false) a'b.txt"])]));
+
+        let options = CommandOptions {
+            action: Action::Print,
+            ..Default::default()
+        };
+        assert_eq!(get_final_command(&options, &mut Tester::default(), &file, &[], true, &entries).unwrap().pipeline, None);
+
+        let entries = &entries[1..2];
+        let options = CommandOptions {
+            noshell: true,
+            session: Session::X11,
+            terminal: String::from("urxvt -fn fixed"),
+            ..Default::default()
+        };
+        let final_command = get_final_command(&options, &mut Tester::default(), &file, &[], false, entries).unwrap();
+        assert_eq!(final_command.pipeline, Some(vec![argv(&[
+            "urxvt", "-fn", "fixed", "-T", "vim -- \"\\$(false) a'b.txt\"", "-e", "sh", "-c", "vim -- \"\\$(false) a'b.txt\"",
+        ])]));
+    }
+
+    #[test]
+    fn test_tokenize_command() {
+        let file = InputFile {
+            filename: String::from("a b.txt"),
+            mimetype: String::from("text/plain"),
+            ..Default::default()
+        };
+        let parts = vec![
+            InputFile {
+                filename: String::from("part.png"),
+                mimetype: String::from("image/png"),
+                ..Default::default()
+            },
+        ];
+
+        let mut expected: Vec<Token> = "show ".chars().map(Token::Char).collect();
+        expected.push(Token::Value(String::from("1")));
+        expected.push(Token::Char(' '));
+        expected.push(Token::Values(vec![String::from("image/png"), String::from("part.png")]));
+        expected.push(Token::Char(' '));
+        expected.push(Token::Value(String::from("text/plain")));
+        expected.push(Token::Char(' '));
+        expected.push(Token::Value(String::from("a b.txt")));
+        expected.push(Token::Char(' '));
+        expected.push(Token::Value(String::from("%")));
+        expected.push(Token::Char('s'));
+        assert_eq!(tokenize_command("show %n %F %t %s \\%s", &file, &parts), expected);
+    }
+
+    #[test]
+    fn test_render_command_adversarial_filenames() {
+        let names = [
+            "plain.txt", "with space.txt", "it's.pdf", "\"quoted\".pdf", "$(touch pwned).pdf",
+            "`touch pwned`.pdf", "${HOME}.pdf", "back\\slash.pdf", "new\nline.pdf", "tab\tstop.pdf",
+            "semi;colon.pdf", "amp&ersand.pdf", "pipe|d.pdf", "<redir>.pdf", "*.pdf", "?.pdf",
+            "[ab].pdf", "~tilde.pdf", "#hash.pdf", "-dash.pdf", "'; touch pwned; '.pdf",
+            "\"; touch pwned; \".pdf", "\\'\\\"\\$.pdf", "ümlaut.pdf", "%s.pdf", "!bang.pdf",
+        ];
+        let templates = [
+            "cat -- %s", "cat -- '%s'", "cat -- \"%s\"", "cat ./\"\"'%s'", "cat ./%s",
+            "cat -- \"it's\" %s 2>/dev/null", "cat -- \\' %s 2>/dev/null",
+        ];
+
+        let tempdir = TempDir::new().unwrap();
+        for name in names.iter() {
+            let (_, mut file) = tempdir.create_file(name).unwrap();
+            write!(file, "{}", name).unwrap();
+            let file = InputFile {
+                filename: name.to_string(),
+                ..Default::default()
+            };
+
+            for template in templates.iter() {
+                let command = render_command(template, &file, &[]);
+                let output = Command::new("sh")
+                    .arg("-c")
+                    .arg(&command)
+                    .current_dir(tempdir.path())
+                    .output()
+                    .unwrap();
+                assert_eq!(String::from_utf8_lossy(&output.stdout), *name, "{:?} from {:?}", command, template);
+            }
+        }
+        assert!(!tempdir.path().join("pwned").exists());
+    }
+
+    #[test]
+    fn test_tester() {
+        let mut tester = Tester::new(Some(Duration::from_millis(200)));
+        assert!(tester.run("", "true", None));
+        assert!(!tester.run("", "false", None));
+        assert!(tester.run("", "true", Some(vec![String::from("true")])));
+        assert!(!tester.run("", "read line", None));
+        assert!(!tester.run("", "/nonexistant/command", Some(vec![String::from("/nonexistant/command")])));
+
+        let start = Instant::now();
+        assert!(!tester.run("", "sleep 10 & sleep 10", None));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        assert_eq!(tester.results.len(), 6);
+        assert_eq!(tester.results[0].command, "true");
+        assert!(tester.results[0].passed());
+        match tester.results[1].outcome {
+            TestOutcome::Exited(status) => assert_eq!(status.code(), Some(1)),
+            ref outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        match tester.results[4].outcome {
+            TestOutcome::Error(_) => {},
+            ref outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        match tester.results[5].outcome {
+            TestOutcome::TimedOut => {},
+            ref outcome => panic!("unexpected outcome: {}", outcome),
+        }
+        assert!(tester.results[5].duration >= Duration::from_millis(200));
+    }
+
+    #[test]
+    fn test_final_command_test_timeout() {
+        let entries: [MailcapEntry; 2] = [
+            MailcapEntry{
+                view: String::from("slow %s"),
+                test: String::from("sleep 10"),
+                ..Default::default()
+            },
+            MailcapEntry{
+                view: String::from("fast %s"),
+                test: String::from("true"),
+                ..Default::default()
+            },
+        ];
+
+        let file = InputFile {
+            filename: String::from("test.txt"),
+            ..Default::default()
+        };
+
+        let mut tester = Tester::new(Some(Duration::from_millis(100)));
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut tester, &file, &[], true, &entries).unwrap().command, "fast test.txt");
+        assert_eq!(tester.results.len(), 2);
+        assert!(!tester.results[0].passed());
+    }
+
+    #[test]
+    fn test_tester_memoize() {
+        let tempdir = TempDir::new().unwrap();
+        let (path, _) = tempdir.create_file("flag").unwrap();
+        let command = format!("test -e '{}'", path.display());
+
+        let mut tester = Tester::new(None);
+        assert!(tester.run("test -e /flag", &command, None));
+        std::fs::remove_file(&path).unwrap();
+        assert!(tester.run("test -e /flag", &command, None));
+        assert!(tester.run("test -e %s", &command, None));
+        match tester.results[1].outcome {
+            TestOutcome::Cached(true) => {},
+            ref outcome => panic!("unexpected outcome: {}", outcome),
+        }
+
+        tester.memoize_file_tests = false;
+        assert!(tester.run("test -e /flag", &command, None));
+        assert!(!tester.run("test -e %s", &command, None));
+        assert!(!tester.run("test -e %s", &command, None));
+        assert_eq!(tester.results.len(), 6);
     }
 
+    #[test]
+    fn test_final_command_memoized_test() {
+        let entries: [MailcapEntry; 3] = [
+            MailcapEntry{
+                view: String::from("first %s"),
+                test: String::from("test -n \"$NONEXISTANT_VARIABLE\""),
+                ..Default::default()
+            },
+            MailcapEntry{
+                view: String::from("second %s"),
+                test: String::from("test -n \"$NONEXISTANT_VARIABLE\""),
+                ..Default::default()
+            },
+            MailcapEntry{
+                view: String::from("third %s"),
+                ..Default::default()
+            },
+        ];
+
+        let file = InputFile {
+            filename: String::from("test.txt"),
+            ..Default::default()
+        };
+
+        let mut tester = Tester::new(None);
+        let options = CommandOptions::default();
+        assert_eq!(get_final_command(&options, &mut tester, &file, &[], true, &entries).unwrap().command, "third test.txt");
+        assert_eq!(get_final_command(&options, &mut tester, &file, &[], true, &entries).unwrap().command, "third test.txt");
+        assert_eq!(tester.results.len(), 4);
+        assert!(tester.results.iter().skip(1).all(|result| matches!(result.outcome, TestOutcome::Cached(false))));
+    }
 }
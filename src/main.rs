@@ -1,133 +1,456 @@
 extern crate atty;
 extern crate regex;
+extern crate run_mailcap_rs;
 
+use std::fs::File;
+use std::io;
 use std::path::Path;
-use std::path::PathBuf;
-use std::process::Command;
+use std::process::{Child, Command, ExitStatus, Stdio};
 use std::env;
 
 mod config;
-mod mailcap;
-mod mimetype;
 
 use config::Config;
+use run_mailcap_rs::{encoding, lint, mailcap, mimetype, terminal};
+use run_mailcap_rs::cache::{MailcapIndex, MimeTypesIndex};
+use run_mailcap_rs::encoding::Encoding;
+use run_mailcap_rs::lint::Severity;
+use run_mailcap_rs::mailcap::{InputFile, ParseMode, TestOutcome, Tester};
+use run_mailcap_rs::mimetype::Detector;
+use run_mailcap_rs::sharedmime::SharedMimeInfo;
+use run_mailcap_rs::tempfile::TempDir;
+
+// Exit codes of run-mailcap-rs itself; otherwise the exit code is the one of
+// the command
+const EXIT_USAGE: u8 = 2;
+const EXIT_NO_HANDLER: u8 = 3;
 
 fn print_usage() {
-    println!("Usage: run-mailcap-rs [OPTION]... [MIME-TYPE:]FILE");
+    println!("Usage: run-mailcap-rs [OPTION]... [MIME-TYPE:]FILE...");
+    println!("       run-mailcap-rs --lint");
+    println!();
+    println!("MIME-TYPE may contain parameters like \"text/plain; charset=utf-8\", that");
+    println!("are substituted for %{{parameter}} in mailcap commands.");
+    println!();
+    println!("If FILE is \"-\", standard input is written to a temporary file, that");
+    println!("is removed after the command has finished.");
+    println!();
+    println!("File names and globs can be mapped to mime types in");
+    println!("$XDG_CONFIG_HOME/run-mailcap-rs/globs with lines like");
+    println!("\"text/x-makefile Makefile GNUmakefile\".");
+    println!();
+    println!("Commands, that need a terminal, are run in a new tmux or screen window,");
+    println!("when running inside tmux (TMUX) or screen (STY), or else in a new");
+    println!("$XTERMCMD (default xterm) on Wayland (WAYLAND_DISPLAY) or X11 (DISPLAY).");
+    println!("The arguments for alacritty, foot, gnome-terminal, kitty, konsole and");
+    println!("wezterm are known, other terminals are expected to understand the ones");
+    println!("of xterm. Templates for terminals can be given in");
+    println!("$XDG_CONFIG_HOME/run-mailcap-rs/terminals with lines like");
+    println!("\"st %t -t '%T' -e sh -c '%c'\", where %t is replaced by $XTERMCMD, %T by");
+    println!("the title and %c by the command.");
+    println!();
+    println!("Defaults for some settings can be given in");
+    println!("$XDG_CONFIG_HOME/run-mailcap-rs/config with \"key = value\" lines. The");
+    println!("keys are pager, terminal, mailcaps and mime-types (colon separated lists");
+    println!("of files), action, timeout and detection (comma separated order of globs,");
+    println!("mime.types, shared-mime-globs, shared-mime-magic, content and file).");
+    println!("Command line options take precedence over PAGER, XTERMCMD and MAILCAPS,");
+    println!("and those over the config file.");
+    println!();
+    println!("Options with a value can also be given as \"--option VALUE\", short");
+    println!("options can be combined (e.g. \"-dn\"). All arguments after \"--\" are");
+    println!("files.");
     println!();
     println!("Options:");
-    println!("    --action=<action>");
+    println!("    -a, --action=<action>");
     println!("        Specify the action performed on the file. Valid actions are:");
     println!("        view, see (same as view), cat (same as view, but only handle");
     println!("        entries with copiousoutput and don't use a pager), edit,");
     println!("        change (same es edit), compose, create (same as compose)");
     println!("        and print.");
-    println!("    --debug");
+    println!("    --encoding=<encoding>");
+    println!("        Decompress the file before handling it. Valid encodings are:");
+    println!("        gzip, bzip2, xz and zstd. Without this option, files with the");
+    println!("        extensions .gz, .bz2, .xz and .zst are decompressed, unless a");
+    println!("        mime type was given for them.");
+    println!("    --part=[MIME-TYPE:]FILE");
+    println!("        Add a part of a multipart message. Can be given multiple times.");
+    println!("        The number of parts is substituted for %n and the list of mime");
+    println!("        types and file names of the parts for %F in mailcap commands.");
+    println!("    -d, --debug");
     println!("        Print some debugging statements. Its more of a tool during");
     println!("        development but may also help to determine whats wrong, when");
     println!("        unexpected actions are performend.");
+    println!("    --lint");
+    println!("        Check all mailcap files for malformed lines, unknown fields,");
+    println!("        unbalanced quotes and entries, that are never used. Exits with");
+    println!("        a non-zero exit code, if errors were found.");
     println!("    --nopager");
     println!("        Ignore \"copiousoutput\" in mailcap files and call the corresponding");
     println!("        command without invoking a pager");
-    println!("    --norun");
+    println!("    -n, --norun");
     println!("        Do not execute the found command, but just print it. The \"test\"");
-    println!("        commands in the mailcap entries are still executed.");
+    println!("        commands in the mailcap entries are still executed. Temporary");
+    println!("        files, the command refers to, are kept and have to be removed");
+    println!("        by the caller.");
+    println!("    --noshell");
+    println!("        Run the command directly instead of with \"sh -c\", if it does not");
+    println!("        need a shell (no pipes, redirections, variables, globs and the");
+    println!("        like). The file name is then passed as a single argument and");
+    println!("        never interpreted by a shell.");
+    println!("    --timeout=<seconds>");
+    println!("        Stop \"test\" commands of mailcap entries after the given number of");
+    println!("        seconds and treat them as failed. The default is 5 seconds, 0");
+    println!("        disables the timeout.");
+    println!("    --retestfiles");
+    println!("        Run \"test\" commands, that contain the file name (%s), again for");
+    println!("        every entry. Without this option, each distinct test command is");
+    println!("        only run once and its result is reused.");
+    println!("    --usefile");
+    println!("        Call the \"file\" command to determine the mime type, if it can not");
+    println!("        be determined from the file name or the built-in detection of");
+    println!("        common file formats.");
+    println!("    --cache");
+    println!("        Keep an index of the mime.types and mailcap files in");
+    println!("        $XDG_CACHE_HOME/run-mailcap-rs, that is rebuilt whenever one of");
+    println!("        the files changes. Mailcap files with errors are never cached.");
+    println!("    --strict");
+    println!("        Fail on malformed lines and unknown fields in mailcap files instead");
+    println!("        of ignoring them. Without this option, they are only reported");
+    println!("        with --debug.");
+    println!("    -h, --help");
+    println!("        Print this help and exit.");
+    println!("    -V, --version");
+    println!("        Print the version and exit.");
+    println!();
+    println!("Exit status:");
+    println!("    The exit status is the one of the last failing command, 2 for invalid");
+    println!("    arguments and 3, if no matching mailcap entry was found for a file.");
+    println!("    The exit status of a command is passed on unchanged, so a command");
+    println!("    exiting with 2 or 3 can not be told apart from those.");
 }
 
-fn main() -> std::process::ExitCode {
-    let config = Config::parse(env::args(), env::vars());
+fn load_indexes(config: &Config) -> (Option<MimeTypesIndex>, Option<MailcapIndex>) {
+    let cache_dir = match config.cache_dir {
+        Some(ref cache_dir) if config.cache => cache_dir,
+        _ => return (None, None),
+    };
+    let mime_paths: Vec<&Path> = config.mime_types_paths.iter().map(|path| path.as_path()).collect();
+    let mailcap_paths: Vec<&Path> = config.mailcap_paths.iter().map(|path| path.as_path()).collect();
+    let mime_types_index = MimeTypesIndex::load(cache_dir, &mime_paths).ok();
+    let mailcap_index = MailcapIndex::load(cache_dir, &mailcap_paths).ok();
 
-    if let Err(_err) = config {
-        print_usage();
-        return std::process::ExitCode::from(2);
+    if config.debug {
+        println!("Cache directory: {}", cache_dir.display());
+        println!("mime.types index: {}", if mime_types_index.is_some() { "used" } else { "not used" });
+        println!("Mailcap index: {}", if mailcap_index.is_some() { "used" } else { "not used" });
+        println!();
     }
-    let mut config = config.unwrap();
+    (mime_types_index, mailcap_index)
+}
+
+fn detect_mimetype(file: &mut InputFile, config: &Config, detector: &Detector) {
+    let (mimetype, source) = detector.detect(&file.filename);
+    file.mimetype = mimetype;
+    file.mimetype_source = String::from(source);
+
+    if config.debug {
+        println!("Determined mime type: {}", file.mimetype);
+        println!("Detected by: {}", file.mimetype_source);
+        println!();
+    }
+}
+
+fn spool_stdin(file: &mut InputFile) -> Result<TempDir, io::Error> {
+    let tempdir = TempDir::new()?;
+    let (path, mut tempfile) = tempdir.create_file("stdin")?;
+    io::copy(&mut io::stdin().lock(), &mut tempfile)?;
+    file.filename = path.to_string_lossy().into_owned();
+    Ok(tempdir)
+}
+
+fn decompress_file(file: &mut InputFile, encoding: &Encoding, inner_filename: &str) -> Result<TempDir, io::Error> {
+    let tempdir = TempDir::new()?;
+    let name = match Path::new(inner_filename).file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => String::from("decompressed"),
+    };
+    let (path, tempfile) = tempdir.create_file(&name)?;
+    encoding::decompress(encoding, &file.filename, tempfile)?;
+    file.filename = path.to_string_lossy().into_owned();
+    Ok(tempdir)
+}
 
-    if config.mimetype == "" {
-        let mut home = PathBuf::from(env::var("HOME").unwrap());
-        home.push(".mime.types");
-
-        let mime_paths: [&Path; 4] = [
-            &home.as_path(),
-            Path::new("/usr/share/etc/mime.types"),
-            Path::new("/usr/local/etc/mime.types"),
-            Path::new("/etc/mime.types"),
-        ];
-
-        config.mimetype = match mimetype::get_type_by_extension(&mime_paths, &config.filename) {
-            Ok(mimetype) => {
-                config.mimetype_source = String::from("mime.types file");
-                mimetype
+// Like a shell pipeline, the exit status is the one of the last program
+fn run_pipeline(pipeline: &[Vec<String>]) -> Result<ExitStatus, io::Error> {
+    let mut children: Vec<Child> = Vec::new();
+    let mut stdin = None;
+    for (index, argv) in pipeline.iter().enumerate() {
+        let mut command = Command::new(&argv[0]);
+        command.args(&argv[1..]);
+        if let Some(stdin) = stdin.take() {
+            command.stdin(Stdio::from(stdin));
+        }
+        if index + 1 < pipeline.len() {
+            command.stdout(Stdio::piped());
+        }
+        let mut child = match command.spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                for mut child in children {
+                    let _ = child.wait();
+                }
+                return Err(e);
             },
-            Err(_e) => String::from(""),
         };
+        stdin = child.stdout.take();
+        children.push(child);
+    }
 
-        if config.mimetype.len() == 0 || config.mimetype == "application/octet-stream" {
-            config.mimetype = match mimetype::get_type_by_magic(&config.filename) {
-                Ok(mimetype) => {
-                    config.mimetype_source = String::from("libmagic");
-                    mimetype
-                },
-                Err(_e) => {
-                    config.mimetype_source = String::from("none");
-                    String::from("application/octet-stream")
-                },
-            };
+    let mut status = Err(io::Error::other("Empty pipeline"));
+    for mut child in children {
+        status = child.wait();
+    }
+    status
+}
+
+fn run_file(config: &Config, detector: &Detector, mailcap_index: &Option<MailcapIndex>, tester: &mut Tester, file: &mut InputFile) -> u8 {
+    if config.debug {
+        println!("File: {}", file.filename);
+    }
+
+    let mut tempdirs = Vec::new();
+    if file.filename == "-" {
+        match spool_stdin(file) {
+            Ok(tempdir) => tempdirs.push(tempdir),
+            Err(e) => {
+                eprintln!("Could not read standard input: {}", e);
+                return 1;
+            },
+        }
+        if config.debug {
+            println!("Spooled standard input to: {}", file.filename);
         }
+    }
 
+    let decompression = if !config.encoding.is_empty() {
+        match Encoding::from(&config.encoding) {
+            Some(encoding) => {
+                let inner_filename = encoding::strip_extension(&encoding, &file.filename).to_string();
+                Some((encoding, inner_filename))
+            },
+            None => {
+                eprintln!("Unsupported encoding: {}", config.encoding);
+                return 1;
+            },
+        }
+    } else if file.mimetype.is_empty() {
+        encoding::get_encoding_by_extension(&file.filename)
+            .map(|(encoding, inner_filename)| (encoding, inner_filename.to_string()))
+    } else {
+        None
+    };
+
+    if let Some((encoding, inner_filename)) = decompression {
+        match decompress_file(file, &encoding, &inner_filename) {
+            Ok(tempdir) => tempdirs.push(tempdir),
+            Err(e) => {
+                eprintln!("Could not decompress {}: {}", file.filename, e);
+                return 1;
+            },
+        }
         if config.debug {
-            println!("Determined mime type: {}", config.mimetype);
-            println!("Detected by: {}", config.mimetype_source);
-            println!();
+            println!("Decompressed {:?} encoded file to: {}", encoding, file.filename);
         }
     }
 
-    let mut home = PathBuf::from(env::var("HOME").unwrap());
-    home.push(".mailcap");
+    if file.mimetype.is_empty() {
+        detect_mimetype(file, config, detector);
+    }
+
+    let mailcap_paths: Vec<&Path> = config.mailcap_paths.iter().map(|path| path.as_path()).collect();
+    let mime_types = mimetype::get_type_hierarchy(&detector.shared_mime_info, &file.mimetype);
+    let mime_types: Vec<&str> = mime_types.iter().map(|mime_type| mime_type.as_str()).collect();
+    if config.debug {
+        println!("Type hierarchy: {}", mime_types.join(" -> "));
+        println!();
+    }
+
+    let mode = if config.strict { ParseMode::Strict } else { ParseMode::Lenient };
+    let mut warnings = Vec::new();
+    let mailcap_entries = match *mailcap_index {
+        Some(ref index) => index.get_entries(&mime_types),
+        None => match mailcap::get_entries(&mailcap_paths, &mime_types, mode, &mut warnings) {
+            Ok(entries) => entries,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            },
+        },
+    };
 
-    let mailcap_paths: [&Path; 5] = [
-        &home.as_path(),
-        Path::new("/etc/mailcap"),
-        Path::new("/usr/share/etc/mailcap"),
-        Path::new("/usr/local/etc/mailcap"),
-        Path::new("/usr/etc/mailcap"),
-    ];
-    let mailcap_entries = mailcap::get_entries(&mailcap_paths, &config.mimetype).unwrap();
+    if config.debug && !warnings.is_empty() {
+        println!("Mailcap warnings:");
+        for warning in &warnings {
+            println!("{}", warning);
+        }
+        println!();
+    }
 
     if config.debug {
         println!("Mailcap entries:");
         for entry in &mailcap_entries {
+            println!("mime type: {}", entry.mime_type);
             println!("view: {}", entry.view);
             println!("edit: {}", entry.edit);
             println!("compose: {}", entry.compose);
             println!("print: {}", entry.print);
             println!("test: {}", entry.test);
+            println!("nametemplate: {}", entry.nametemplate);
             println!("needsterminal: {}", entry.needsterminal);
             println!("copiousoutput: {}", entry.copiousoutput);
             println!();
         }
     }
 
-    if let Some(command) = mailcap::get_final_command(&config, atty::is(atty::Stream::Stdout), &mailcap_entries) {
+    let final_command = mailcap::get_final_command(&config.command_options(), tester, file, &config.parts, atty::is(atty::Stream::Stdout), &mailcap_entries);
+
+    if config.debug && !tester.results.is_empty() {
+        println!("Test commands:");
+        for result in &tester.results {
+            println!("{}", result.command);
+            match result.outcome {
+                TestOutcome::Cached(_) => println!("{}", result.outcome),
+                _ => println!("{} after {} ms", result.outcome, result.duration.as_millis()),
+            }
+        }
+        println!();
+    }
+    tester.results.clear();
+
+    if let Some(final_command) = final_command {
         if config.norun {
-            println!("{}", command);
-            return std::process::ExitCode::from(0)
+            println!("{}", final_command.command);
+            // The printed command may refer to files in them
+            for tempdir in tempdirs.into_iter().chain(final_command.tempdir) {
+                let path = tempdir.keep();
+                if config.debug {
+                    println!("Kept temporary directory: {}", path.display());
+                }
+            }
+            0
         } else {
-            let status = Command::new("sh")
-                .arg("-c")
-                .arg(command)
-                .status();
-            return match status {
+            let status = match final_command.pipeline {
+                Some(ref pipeline) => {
+                    if config.debug {
+                        println!("Running without shell: {:?}", pipeline);
+                    }
+                    run_pipeline(pipeline)
+                },
+                None => Command::new("sh")
+                    .arg("-c")
+                    .arg(&final_command.command)
+                    .status(),
+            };
+            match status {
                 Ok(status) => {
                     match status.code() {
-                        Some(code) => std::process::ExitCode::from(code as u8),
-                        None       => std::process::ExitCode::from(1),
+                        Some(code) => code as u8,
+                        None       => 1,
                     }
                 },
-                Err(_e) => std::process::ExitCode::from(1),
+                Err(_e) => 1,
             }
         }
+    } else {
+        eprintln!("No matching mailcap entry found for {} ({})", file.filename, file.mimetype);
+        EXIT_NO_HANDLER
     }
-    std::process::ExitCode::from(0)
 }
 
+fn main() -> std::process::ExitCode {
+    let config = Config::parse(env::args(), env::vars());
+
+    if let Err(err) = config {
+        eprintln!("run-mailcap-rs: {}", err);
+        eprintln!("Try 'run-mailcap-rs --help' for more information.");
+        return std::process::ExitCode::from(EXIT_USAGE);
+    }
+    let mut config = config.unwrap();
+    if config.help {
+        print_usage();
+        return std::process::ExitCode::from(0);
+    }
+    if config.version {
+        println!("run-mailcap-rs {}", env!("CARGO_PKG_VERSION"));
+        return std::process::ExitCode::from(0);
+    }
+    let terminals_paths: Vec<&Path> = config.terminals_paths.iter().map(|path| path.as_path()).collect();
+    config.terminal_templates = terminal::load_templates(&terminals_paths);
+
+    if config.debug {
+        match config.config_file {
+            Some(ref path) => println!("Config file: {}", path.display()),
+            None => println!("Config file: none"),
+        }
+        println!("Settings:");
+        for (name, value, origin) in config.settings() {
+            println!("{}: {} ({})", name, value, origin);
+        }
+        println!();
+        println!("Mailcap files:");
+        for path in &config.mailcap_paths {
+            match File::open(path) {
+                Ok(_file) => println!("{} (consulted)", path.display()),
+                Err(e) => println!("{} ({})", path.display(), e),
+            }
+        }
+        println!();
+        println!("Terminal session: {:?}", config.session);
+        println!("Terminal template: {}", terminal::find_template(&config.terminal_templates, &config.xtermcmd));
+        println!();
+    }
+
+    if config.lint {
+        let mailcap_paths: Vec<&Path> = config.mailcap_paths.iter().map(|path| path.as_path()).collect();
+        let messages = lint::lint(&mailcap_paths);
+        for message in &messages {
+            println!("{}", message);
+        }
+        if messages.iter().any(|message| message.severity == Severity::Error) {
+            return std::process::ExitCode::from(1);
+        }
+        return std::process::ExitCode::from(0);
+    }
+
+    let (mime_types_index, mailcap_index) = load_indexes(&config);
+    let detector = Detector {
+        globs_paths: config.mime_globs_paths.clone(),
+        mime_types_paths: config.mime_types_paths.clone(),
+        mime_types_index,
+        shared_mime_info: SharedMimeInfo::load(&config.shared_mime_paths),
+        methods: config.detection.clone(),
+        use_file: config.usefile,
+    };
+
+    let mut parts = std::mem::take(&mut config.parts);
+    for part in &mut parts {
+        if part.mimetype.is_empty() {
+            detect_mimetype(part, &config, &detector);
+        }
+    }
+    config.parts = parts;
+
+    let mut tester = Tester::new(config.test_timeout);
+    tester.memoize_file_tests = !config.retestfiles;
+
+    let mut files = std::mem::take(&mut config.files);
+    let mut result = 0;
+    for file in &mut files {
+        let code = run_file(&config, &detector, &mailcap_index, &mut tester, file);
+        if code != 0 {
+            result = code;
+        }
+    }
+    std::process::ExitCode::from(result)
+}
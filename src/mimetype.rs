@@ -1,9 +1,253 @@
-use std::path::Path;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
 use std::fs::File;
 use std::io::{self, BufReader};
 use std::io::prelude::*;
 use std::process::Command;
 
+use super::cache::MimeTypesIndex;
+use super::glob::{glob_match, is_glob};
+use super::sharedmime::SharedMimeInfo;
+
+const BUILTIN_ALIASES: [(&str, &str); 12] = [
+    ("application/acrobat", "application/pdf"),
+    ("application/x-pdf", "application/pdf"),
+    ("application/x-gzip", "application/gzip"),
+    ("application/x-zip-compressed", "application/zip"),
+    ("application/javascript", "text/javascript"),
+    ("application/x-javascript", "text/javascript"),
+    ("audio/mp3", "audio/mpeg"),
+    ("audio/x-mp3", "audio/mpeg"),
+    ("image/jpg", "image/jpeg"),
+    ("image/pjpeg", "image/jpeg"),
+    ("text/xml", "application/xml"),
+    ("text/x-markdown", "text/markdown"),
+];
+
+const BUILTIN_SUBCLASSES: [(&str, &str); 14] = [
+    ("application/xhtml+xml", "text/html"),
+    ("application/xhtml+xml", "application/xml"),
+    ("application/xml", "text/plain"),
+    ("application/json", "text/plain"),
+    ("application/x-shellscript", "text/plain"),
+    ("application/x-compressed-tar", "application/gzip"),
+    ("application/x-bzip-compressed-tar", "application/x-bzip2"),
+    ("application/x-xz-compressed-tar", "application/x-xz"),
+    ("application/epub+zip", "application/zip"),
+    ("application/vnd.oasis.opendocument.text", "application/zip"),
+    ("application/vnd.oasis.opendocument.spreadsheet", "application/zip"),
+    ("application/vnd.openxmlformats-officedocument.wordprocessingml.document", "application/zip"),
+    ("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", "application/zip"),
+    ("application/x-sharedlib", "application/x-executable"),
+];
+
+fn unalias<'a>(shared_mime_info: &'a SharedMimeInfo, mimetype: &'a str) -> &'a str {
+    let mimetype = shared_mime_info.unalias(mimetype);
+    BUILTIN_ALIASES.iter()
+        .find(|&&(alias, _)| alias == mimetype)
+        .map_or(mimetype, |&(_, canonical)| canonical)
+}
+
+fn get_parents(shared_mime_info: &SharedMimeInfo, mimetype: &str) -> Vec<String> {
+    let mut parents: Vec<String> = shared_mime_info.get_parents(mimetype).to_vec();
+    parents.extend(BUILTIN_SUBCLASSES.iter()
+        .filter(|&&(subclass, _)| subclass == mimetype)
+        .map(|&(_, parent)| parent.to_string()));
+
+    if mimetype.ends_with("+xml") {
+        parents.push(String::from("application/xml"));
+    } else if mimetype.ends_with("+json") {
+        parents.push(String::from("application/json"));
+    } else if mimetype.ends_with("+zip") {
+        parents.push(String::from("application/zip"));
+    }
+    if mimetype.starts_with("text/") && mimetype != "text/plain" {
+        parents.push(String::from("text/plain"));
+    }
+    parents
+}
+
+pub fn get_type_hierarchy(shared_mime_info: &SharedMimeInfo, mimetype: &str) -> Vec<String> {
+    let mut hierarchy: Vec<String> = Vec::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(mimetype.to_string());
+
+    while let Some(mimetype) = queue.pop_front() {
+        let mimetype = unalias(shared_mime_info, &mimetype).to_string();
+        if mimetype == "application/octet-stream" || hierarchy.contains(&mimetype) {
+            continue;
+        }
+        queue.extend(get_parents(shared_mime_info, &mimetype));
+        hierarchy.push(mimetype);
+    }
+
+    // Every type of file content is also an octet stream, so it is always the last resort
+    if !mimetype.starts_with("inode/") {
+        hierarchy.push(String::from("application/octet-stream"));
+    }
+    hierarchy
+}
+
+const SNIFF_LENGTH: usize = 8192;
+
+fn get_zip_type(data: &[u8]) -> &'static str {
+    // ODF and EPUB store their mime type uncompressed in a first file named "mimetype"
+    if data.len() >= 38 && &data[30..38] == b"mimetype" {
+        let size = u32::from_le_bytes([data[18], data[19], data[20], data[21]]) as usize;
+        let extra_length = u16::from_le_bytes([data[28], data[29]]) as usize;
+        let start = 38 + extra_length;
+        let mimetype = data.get(start..start + size).unwrap_or(b"");
+        match mimetype {
+            b"application/vnd.oasis.opendocument.text" => return "application/vnd.oasis.opendocument.text",
+            b"application/vnd.oasis.opendocument.spreadsheet" => return "application/vnd.oasis.opendocument.spreadsheet",
+            b"application/vnd.oasis.opendocument.presentation" => return "application/vnd.oasis.opendocument.presentation",
+            b"application/vnd.oasis.opendocument.graphics" => return "application/vnd.oasis.opendocument.graphics",
+            b"application/epub+zip" => return "application/epub+zip",
+            _ => {},
+        }
+    }
+
+    if contains(data, b"[Content_Types].xml") || contains(data, b"_rels/.rels") {
+        if contains(data, b"word/") {
+            return "application/vnd.openxmlformats-officedocument.wordprocessingml.document";
+        } else if contains(data, b"xl/") {
+            return "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet";
+        } else if contains(data, b"ppt/") {
+            return "application/vnd.openxmlformats-officedocument.presentationml.presentation";
+        }
+    }
+    "application/zip"
+}
+
+fn contains(data: &[u8], needle: &[u8]) -> bool {
+    data.windows(needle.len()).any(|window| window == needle)
+}
+
+fn starts_with_ignore_case(data: &[u8], prefix: &[u8]) -> bool {
+    data.len() >= prefix.len() && data[..prefix.len()].eq_ignore_ascii_case(prefix)
+}
+
+fn get_markup_type(data: &[u8]) -> Option<&'static str> {
+    let start = data.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(data.len());
+    let data = &data[start..];
+
+    if starts_with_ignore_case(data, b"<!doctype html") || starts_with_ignore_case(data, b"<html") ||
+        starts_with_ignore_case(data, b"<head") || starts_with_ignore_case(data, b"<body") {
+        Some("text/html")
+    } else if data.starts_with(b"<?xml") {
+        if contains(data, b"<svg") {
+            Some("image/svg+xml")
+        } else if contains(data, b"<html") {
+            Some("application/xhtml+xml")
+        } else {
+            Some("application/xml")
+        }
+    } else if data.starts_with(b"<svg") {
+        Some("image/svg+xml")
+    } else {
+        None
+    }
+}
+
+fn is_text(data: &[u8]) -> bool {
+    if data.contains(&0) {
+        return false;
+    }
+    let text = match std::str::from_utf8(data) {
+        Ok(text) => text,
+        // A multibyte sequence may have been cut off at the end of the buffer
+        Err(e) if e.error_len().is_none() => std::str::from_utf8(&data[..e.valid_up_to()]).unwrap(),
+        Err(_e) => return false,
+    };
+    text.chars().all(|c| !c.is_control() || c.is_whitespace() || c == '\x1b' || c == '\x0c')
+}
+
+pub fn get_type_by_data(data: &[u8]) -> &'static str {
+    let magic: [(&[u8], &'static str); 22] = [
+        (b"%PDF-", "application/pdf"),
+        (b"%!PS", "application/postscript"),
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"II*\x00", "image/tiff"),
+        (b"MM\x00*", "image/tiff"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"BZh", "application/x-bzip2"),
+        (b"\xfd7zXZ\x00", "application/x-xz"),
+        (b"\x28\xb5\x2f\xfd", "application/zstd"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"Rar!\x1a\x07", "application/vnd.rar"),
+        (b"OggS", "audio/ogg"),
+        (b"fLaC", "audio/flac"),
+        (b"ID3", "audio/mpeg"),
+        (b"{\\rtf", "application/rtf"),
+        (b"\x1a\x45\xdf\xa3", "video/x-matroska"),
+        (b"\xef\xbb\xbf", "text/plain"),
+        (b"\xff\xfe", "text/plain"),
+        (b"\xfe\xff", "text/plain"),
+    ];
+
+    if data.is_empty() {
+        return "application/x-zerosize";
+    }
+
+    if data.starts_with(b"PK\x03\x04") {
+        return get_zip_type(data);
+    }
+    if data.starts_with(b"\x7fELF") && data.len() > 17 {
+        // e_type is stored in the byte order given by EI_DATA
+        let elf_type = if data[5] == 2 { data[17] } else { data[16] };
+        return match elf_type {
+            1 => "application/x-object",
+            3 => "application/x-sharedlib",
+            4 => "application/x-core",
+            _ => "application/x-executable",
+        };
+    }
+    if data.starts_with(b"RIFF") && data.len() >= 12 {
+        match &data[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/x-wav",
+            b"AVI " => return "video/x-msvideo",
+            _ => {},
+        }
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        return match &data[8..12] {
+            b"qt  " => "video/quicktime",
+            b"M4A " => "audio/mp4",
+            _ => "video/mp4",
+        };
+    }
+    if data.len() > 262 && &data[257..262] == b"ustar" {
+        return "application/x-tar";
+    }
+
+    let text = if data.starts_with(b"\xef\xbb\xbf") { &data[3..] } else { data };
+    if let Some(mimetype) = get_markup_type(text) {
+        return mimetype;
+    }
+
+    for &(prefix, mimetype) in magic.iter() {
+        if data.starts_with(prefix) {
+            return mimetype;
+        }
+    }
+
+    if is_text(data) {
+        "text/plain"
+    } else {
+        "application/octet-stream"
+    }
+}
+
+pub fn get_type_by_content(filename: &str) -> Result<String, io::Error> {
+    let mut data = Vec::with_capacity(SNIFF_LENGTH);
+    File::open(filename)?.take(SNIFF_LENGTH as u64).read_to_end(&mut data)?;
+    Ok(get_type_by_data(&data).to_string())
+}
+
 pub fn get_type_by_magic(filename: &str) -> Result<String, io::Error> {
     if let Ok(output) = Command::new("file")
         .arg("-E")
@@ -27,17 +271,31 @@ pub fn get_type_by_magic(filename: &str) -> Result<String, io::Error> {
     }
 }
 
+fn get_basename(filename: &str) -> &str {
+    filename.rsplit('/').next().unwrap()
+}
+
+// All extensions of the file name, longest first: "a.tar.gz" has "tar.gz" and "gz"
+pub fn get_extensions(filename: &str) -> Vec<String> {
+    // Leading dots of hidden files like ".bashrc" do not start an extension
+    let basename = get_basename(filename).trim_start_matches('.');
+    basename.match_indices('.')
+        .map(|(index, _)| basename[index + 1..].to_lowercase())
+        .filter(|extension| !extension.is_empty())
+        .collect()
+}
+
 pub fn get_type_by_extension(mime_paths: &[&Path], filename: &str) -> Result<String, io::Error> {
     let mut file_opened = false;
 
-    if !filename.contains('.') {
+    let extensions = get_extensions(filename);
+    if extensions.is_empty() {
         return Ok(String::from("application/octet-stream"));
     }
 
-    let extension = filename.rsplit('.').next().unwrap();
-
+    let mut best: Option<(usize, String)> = None;
     for path in mime_paths {
-        let file = match File::open(&path) {
+        let file = match File::open(path) {
             Ok(file) => file,
             Err(_e) => continue,
         };
@@ -56,24 +314,192 @@ pub fn get_type_by_extension(mime_paths: &[&Path], filename: &str) -> Result<Str
                     continue;
                 }
                 for item in items {
-                    if extension.to_lowercase() == item {
-                        return Ok(String::from(mime));
+                    if let Some(index) = extensions.iter().position(|extension| extension == item) {
+                        if index == 0 {
+                            return Ok(String::from(mime));
+                        }
+                        if best.as_ref().is_none_or(|&(best_index, _)| index < best_index) {
+                            best = Some((index, String::from(mime)));
+                        }
                     }
                 }
             }
         }
     }
 
-    if file_opened {
+    if let Some((_, mime)) = best {
+        Ok(mime)
+    } else if file_opened {
         Ok(String::from("application/octet-stream"))
     } else {
         Err(io::Error::new(io::ErrorKind::NotFound, "No usable mime.types file found"))
     }
 }
 
+pub fn get_type_by_pattern(pattern_paths: &[&Path], filename: &str) -> Result<String, io::Error> {
+    let mut file_opened = false;
+    let basename = get_basename(filename);
+
+    // Full file names win over globs, longer globs over shorter ones
+    let mut best: Option<(bool, usize, String)> = None;
+    for path in pattern_paths {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_e) => continue,
+        };
+        file_opened = true;
+
+        let file = BufReader::new(file);
+
+        for line in file.lines() {
+            let line = line?;
+            if line.starts_with('#') {
+                continue;
+            }
+            let mut items = line.split_whitespace();
+            if let Some(mime) = items.next() {
+                if mime.matches('/').count() != 1 {
+                    continue;
+                }
+                for item in items {
+                    if !glob_match(item, basename, false) {
+                        continue;
+                    }
+                    let rank = (!is_glob(item), item.len());
+                    if best.as_ref().is_none_or(|&(literal, length, _)| rank > (literal, length)) {
+                        best = Some((rank.0, rank.1, String::from(mime)));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some((_, _, mime)) = best {
+        Ok(mime)
+    } else if file_opened {
+        Ok(String::from("application/octet-stream"))
+    } else {
+        Err(io::Error::new(io::ErrorKind::NotFound, "No usable globs file found"))
+    }
+}
+
+fn is_unknown(mimetype: &str) -> bool {
+    mimetype.is_empty() || mimetype == "application/octet-stream"
+}
+
+// The ways to determine the mime type of a file
+#[derive(Debug)]
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+pub enum Method {
+    UserGlobs,
+    MimeTypes,
+    SharedMimeGlobs,
+    SharedMimeMagic,
+    Content,
+    Libmagic,
+}
+
+pub const METHODS: [Method; 6] = [
+    Method::UserGlobs,
+    Method::MimeTypes,
+    Method::SharedMimeGlobs,
+    Method::SharedMimeMagic,
+    Method::Content,
+    Method::Libmagic,
+];
+
+impl Method {
+    pub fn from_name(name: &str) -> Option<Method> {
+        METHODS.iter().cloned().find(|method| method.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::UserGlobs => "globs",
+            Method::MimeTypes => "mime.types",
+            Method::SharedMimeGlobs => "shared-mime-globs",
+            Method::SharedMimeMagic => "shared-mime-magic",
+            Method::Content => "content",
+            Method::Libmagic => "file",
+        }
+    }
+
+    fn source(self) -> &'static str {
+        match self {
+            Method::UserGlobs => "user globs file",
+            Method::MimeTypes => "mime.types file",
+            Method::SharedMimeGlobs => "shared-mime-info globs",
+            Method::SharedMimeMagic => "shared-mime-info magic",
+            Method::Content => "content sniffing",
+            Method::Libmagic => "libmagic",
+        }
+    }
+}
+
+// All sources for the mime type of a file, tried in order until one of them
+// gives a more specific type than application/octet-stream
+#[derive(Debug)]
+pub struct Detector {
+    pub globs_paths: Vec<PathBuf>,
+    pub mime_types_paths: Vec<PathBuf>,
+    pub mime_types_index: Option<MimeTypesIndex>,
+    pub shared_mime_info: SharedMimeInfo,
+    pub methods: Vec<Method>,
+    // libmagic is only asked, when this is set
+    pub use_file: bool,
+}
+
+impl Default for Detector {
+    fn default() -> Self {
+        Detector {
+            globs_paths: Vec::new(),
+            mime_types_paths: Vec::new(),
+            mime_types_index: None,
+            shared_mime_info: SharedMimeInfo::default(),
+            methods: METHODS.to_vec(),
+            use_file: false,
+        }
+    }
+}
+
+impl Detector {
+    fn detect_with(&self, method: Method, filename: &str) -> Option<String> {
+        match method {
+            Method::UserGlobs => {
+                let globs_paths: Vec<&Path> = self.globs_paths.iter().map(|path| path.as_path()).collect();
+                get_type_by_pattern(&globs_paths, filename).ok()
+            },
+            Method::MimeTypes => match self.mime_types_index {
+                Some(ref index) => index.get_type_by_extension(filename).ok(),
+                None => {
+                    let mime_paths: Vec<&Path> = self.mime_types_paths.iter().map(|path| path.as_path()).collect();
+                    get_type_by_extension(&mime_paths, filename).ok()
+                },
+            },
+            Method::SharedMimeGlobs => self.shared_mime_info.get_type_by_glob(filename).map(|mimetype| mimetype.to_string()),
+            Method::SharedMimeMagic => self.shared_mime_info.get_type_by_content(filename).ok().and_then(|mimetype| mimetype),
+            Method::Content => get_type_by_content(filename).ok(),
+            Method::Libmagic if self.use_file => get_type_by_magic(filename).ok(),
+            Method::Libmagic => None,
+        }
+    }
+
+    // Returns the mime type together with the source, that determined it
+    pub fn detect(&self, filename: &str) -> (String, &'static str) {
+        for &method in &self.methods {
+            if let Some(mimetype) = self.detect_with(method, filename) {
+                if !is_unknown(&mimetype) {
+                    return (mimetype, method.source());
+                }
+            }
+        }
+        (String::from("application/octet-stream"), "none")
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use std::path::PathBuf;
     use super::*;
 
     #[test]
@@ -81,7 +507,7 @@ mod tests {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push("tests/data/mime.types");
 
-        let mime_paths: [&Path; 1] = [&path.as_path()];
+        let mime_paths: [&Path; 1] = [path.as_path()];
 
         assert_eq!(get_type_by_extension(&mime_paths, "test.mp4").unwrap(), "video/mp4");
         assert_eq!(get_type_by_extension(&mime_paths, "test.MP4").unwrap(), "video/mp4");
@@ -90,13 +516,196 @@ mod tests {
         assert_eq!(get_type_by_extension(&mime_paths, "test.html").unwrap(), "application/octet-stream");
     }
 
+    #[test]
+    fn test_mime_types_compound_extensions() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mime.types");
+
+        let mime_paths: [&Path; 1] = [path.as_path()];
+
+        assert_eq!(get_type_by_extension(&mime_paths, "archive.tar.gz").unwrap(), "application/x-compressed-tar");
+        assert_eq!(get_type_by_extension(&mime_paths, "archive.gz").unwrap(), "application/gzip");
+        assert_eq!(get_type_by_extension(&mime_paths, "foo.bar.tar.gz").unwrap(), "application/x-compressed-tar");
+        assert_eq!(get_type_by_extension(&mime_paths, "archive.TAR.GZ").unwrap(), "application/x-compressed-tar");
+        assert_eq!(get_type_by_extension(&mime_paths, "dir.d/test").unwrap(), "application/octet-stream");
+        assert_eq!(get_type_by_extension(&mime_paths, "dir.txt/test.mp4").unwrap(), "video/mp4");
+        assert_eq!(get_type_by_extension(&mime_paths, ".txt").unwrap(), "application/octet-stream");
+        assert_eq!(get_type_by_extension(&mime_paths, "dir/.bashrc.txt").unwrap(), "text/plain");
+        assert_eq!(get_type_by_extension(&mime_paths, "test.").unwrap(), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_mime_globs() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/globs");
+
+        let pattern_paths: [&Path; 1] = [path.as_path()];
+
+        assert_eq!(get_type_by_pattern(&pattern_paths, "dir/Makefile").unwrap(), "text/x-makefile");
+        assert_eq!(get_type_by_pattern(&pattern_paths, "makefile").unwrap(), "text/x-makefile");
+        assert_eq!(get_type_by_pattern(&pattern_paths, "Makefile.am").unwrap(), "text/x-automake");
+        assert_eq!(get_type_by_pattern(&pattern_paths, "README").unwrap(), "text/plain");
+        assert_eq!(get_type_by_pattern(&pattern_paths, "README.md").unwrap(), "text/plain");
+        assert_eq!(get_type_by_pattern(&pattern_paths, "notes.MD").unwrap(), "text/markdown");
+        assert_eq!(get_type_by_pattern(&pattern_paths, "README.foo").unwrap(), "text/plain");
+        assert_eq!(get_type_by_pattern(&pattern_paths, ".bashrc").unwrap(), "application/x-shellscript");
+        assert_eq!(get_type_by_pattern(&pattern_paths, "test.pdf").unwrap(), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_mime_globs_nonexistant_file() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/globs.");
+
+        let pattern_paths: [&Path; 1] = [path.as_path()];
+
+        assert_eq!(get_type_by_pattern(&pattern_paths, "Makefile").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_type_by_data() {
+        assert_eq!(get_type_by_data(b""), "application/x-zerosize");
+        assert_eq!(get_type_by_data(b"%PDF-1.4\n%\xe2\xe3\xcf\xd3"), "application/pdf");
+        assert_eq!(get_type_by_data(b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR"), "image/png");
+        assert_eq!(get_type_by_data(b"\xff\xd8\xff\xe0\x00\x10JFIF"), "image/jpeg");
+        assert_eq!(get_type_by_data(b"GIF89a\x01\x00"), "image/gif");
+        assert_eq!(get_type_by_data(b"\x1f\x8b\x08\x00"), "application/gzip");
+        assert_eq!(get_type_by_data(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x02\x00\x3e\x00"), "application/x-executable");
+        assert_eq!(get_type_by_data(b"\x7fELF\x02\x01\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x03\x00\x3e\x00"), "application/x-sharedlib");
+        assert_eq!(get_type_by_data(b"RIFF\x00\x00\x00\x00WEBPVP8 "), "image/webp");
+        assert_eq!(get_type_by_data(b"\x00\x00\x00\x18ftypmp42"), "video/mp4");
+        assert_eq!(get_type_by_data(b"  <!DOCTYPE html>\n<html>"), "text/html");
+        assert_eq!(get_type_by_data(b"\xef\xbb\xbf<html><body>"), "text/html");
+        assert_eq!(get_type_by_data(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\">"), "image/svg+xml");
+        assert_eq!(get_type_by_data(b"<?xml version=\"1.0\"?>\n<root/>"), "application/xml");
+        assert_eq!(get_type_by_data(b"\xff\xfeh\x00i\x00"), "text/plain");
+        assert_eq!(get_type_by_data("Hello, W\u{f6}rld!\n".as_bytes()), "text/plain");
+        assert_eq!(get_type_by_data(b"Hello, W\xc3"), "text/plain");
+        assert_eq!(get_type_by_data(b"Hello\x00World"), "application/octet-stream");
+        assert_eq!(get_type_by_data(b"\x01\x02\x03\x04"), "application/octet-stream");
+    }
+
+    #[test]
+    fn test_type_by_data_zip() {
+        let mut odt = b"PK\x03\x04\x14\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x27\x00\x00\x00\x27\x00\x00\x00\x08\x00\x00\x00".to_vec();
+        odt.extend_from_slice(b"mimetypeapplication/vnd.oasis.opendocument.textPK\x03\x04");
+        assert_eq!(get_type_by_data(&odt), "application/vnd.oasis.opendocument.text");
+
+        let mut docx = b"PK\x03\x04\x14\x00\x06\x00\x08\x00\x00\x00\x21\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x13\x00\x00\x00".to_vec();
+        docx.extend_from_slice(b"[Content_Types].xml\x00\x00PK\x03\x04word/document.xml");
+        assert_eq!(get_type_by_data(&docx), "application/vnd.openxmlformats-officedocument.wordprocessingml.document");
+
+        let mut zip = b"PK\x03\x04\x14\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x07\x00\x00\x00".to_vec();
+        zip.extend_from_slice(b"foo.txt");
+        assert_eq!(get_type_by_data(&zip), "application/zip");
+    }
+
+    #[test]
+    fn test_type_by_content() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/compressed.txt.gz");
+        assert_eq!(get_type_by_content(path.to_str().unwrap()).unwrap(), "application/gzip");
+
+        path.set_file_name("mime.types");
+        assert_eq!(get_type_by_content(path.to_str().unwrap()).unwrap(), "text/plain");
+
+        path.set_file_name("mime.types.");
+        assert_eq!(get_type_by_content(path.to_str().unwrap()).unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_type_hierarchy() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mime");
+        let shared_mime_info = SharedMimeInfo::load(&[path]);
+
+        assert_eq!(get_type_hierarchy(&shared_mime_info, "application/x-pdf"), vec![
+            "application/pdf",
+            "application/octet-stream",
+        ]);
+        assert_eq!(get_type_hierarchy(&shared_mime_info, "application/xhtml+xml"), vec![
+            "application/xhtml+xml",
+            "application/xml",
+            "text/html",
+            "text/plain",
+            "application/octet-stream",
+        ]);
+        assert_eq!(get_type_hierarchy(&shared_mime_info, "image/svg+xml"), vec![
+            "image/svg+xml",
+            "application/xml",
+            "text/plain",
+            "application/octet-stream",
+        ]);
+        assert_eq!(get_type_hierarchy(&shared_mime_info, "application/octet-stream"), vec![
+            "application/octet-stream",
+        ]);
+        assert_eq!(get_type_hierarchy(&shared_mime_info, "inode/directory"), vec![
+            "inode/directory",
+        ]);
+    }
+
+    #[test]
+    fn test_type_hierarchy_builtin() {
+        let shared_mime_info = SharedMimeInfo::default();
+
+        assert_eq!(get_type_hierarchy(&shared_mime_info, "image/jpg"), vec![
+            "image/jpeg",
+            "application/octet-stream",
+        ]);
+        assert_eq!(get_type_hierarchy(&shared_mime_info, "application/xhtml+xml"), vec![
+            "application/xhtml+xml",
+            "text/html",
+            "application/xml",
+            "text/plain",
+            "application/octet-stream",
+        ]);
+        assert_eq!(get_type_hierarchy(&shared_mime_info, "text/csv"), vec![
+            "text/csv",
+            "text/plain",
+            "application/octet-stream",
+        ]);
+    }
+
     #[test]
     fn test_mime_types_nonexistant_file() {
         let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
         path.push("tests/data/mime.types.");
 
-        let mime_paths: [&Path; 1] = [&path.as_path()];
+        let mime_paths: [&Path; 1] = [path.as_path()];
 
         assert_eq!(get_type_by_extension(&mime_paths, "test.txt").unwrap_err().kind(), io::ErrorKind::NotFound);
     }
+
+    #[test]
+    fn test_detector() {
+        let data = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data");
+        let detector = Detector {
+            globs_paths: vec![data.join("globs")],
+            mime_types_paths: vec![data.join("mime.types")],
+            shared_mime_info: SharedMimeInfo::load(&[data.join("mime")]),
+            ..Default::default()
+        };
+
+        assert_eq!(detector.detect("dir/Makefile"), (String::from("text/x-makefile"), "user globs file"));
+        assert_eq!(detector.detect("test.mp4"), (String::from("video/mp4"), "mime.types file"));
+        assert_eq!(detector.detect("test.pdf"), (String::from("application/pdf"), "shared-mime-info globs"));
+        assert_eq!(detector.detect(&data.join("mailcap").to_string_lossy()), (String::from("text/plain"), "content sniffing"));
+        assert_eq!(detector.detect("nonexistant"), (String::from("application/octet-stream"), "none"));
+
+        let detector = Detector {
+            methods: vec![Method::MimeTypes, Method::UserGlobs],
+            ..detector
+        };
+        assert_eq!(detector.detect("Makefile.am"), (String::from("text/x-automake"), "user globs file"));
+        assert_eq!(detector.detect("README.txt"), (String::from("text/plain"), "mime.types file"));
+        assert_eq!(detector.detect(&data.join("mailcap").to_string_lossy()), (String::from("application/octet-stream"), "none"));
+    }
+
+    #[test]
+    fn test_method_names() {
+        for &method in &METHODS {
+            assert_eq!(Method::from_name(method.name()), Some(method));
+        }
+        assert_eq!(Method::from_name("magic"), None);
+    }
 }
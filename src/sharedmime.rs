@@ -0,0 +1,393 @@
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+use super::glob::glob_match;
+
+#[derive(Debug)]
+struct Glob {
+    weight: u32,
+    mimetype: String,
+    pattern: String,
+    case_sensitive: bool,
+}
+
+#[derive(Debug)]
+struct MagicRule {
+    offset: usize,
+    range: usize,
+    value: Vec<u8>,
+    mask: Option<Vec<u8>>,
+    children: Vec<MagicRule>,
+}
+
+#[derive(Debug)]
+struct MagicSection {
+    priority: u32,
+    mimetype: String,
+    rules: Vec<MagicRule>,
+}
+
+#[derive(Debug)]
+#[derive(Default)]
+struct Magic {
+    sections: Vec<MagicSection>,
+    extent: usize,
+}
+
+// The aliases and subclasses are needed for every file, the globs and magic
+// only, when the mime type is detected with them, so they are read on first
+// use
+#[derive(Debug)]
+#[derive(Default)]
+pub struct SharedMimeInfo {
+    paths: Vec<PathBuf>,
+    globs: OnceCell<Vec<Glob>>,
+    magic: OnceCell<Magic>,
+    aliases: HashMap<String, String>,
+    subclasses: HashMap<String, Vec<String>>,
+}
+
+impl MagicRule {
+    fn matches(&self, data: &[u8]) -> bool {
+        let found = (self.offset..self.offset + self.range).any(|offset| {
+            match data.get(offset..offset + self.value.len()) {
+                Some(window) => match self.mask {
+                    Some(ref mask) => window.iter().zip(self.value.iter()).zip(mask.iter())
+                        .all(|((&byte, &value), &mask)| byte & mask == value & mask),
+                    None => window == &self.value[..],
+                },
+                None => false,
+            }
+        });
+        found && (self.children.is_empty() || self.children.iter().any(|child| child.matches(data)))
+    }
+
+    fn extent(&self) -> usize {
+        let own = self.offset + self.range + self.value.len();
+        self.children.iter().map(|child| child.extent()).fold(own, usize::max)
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn parse_number(data: &[u8], pos: &mut usize) -> Option<usize> {
+    let start = *pos;
+    while *pos < data.len() && data[*pos].is_ascii_digit() {
+        *pos += 1;
+    }
+    std::str::from_utf8(&data[start..*pos]).ok()?.parse().ok()
+}
+
+fn parse_magic_rule(data: &[u8], pos: &mut usize) -> Result<(usize, MagicRule), io::Error> {
+    let indent = if data[*pos] == b'>' { 0 } else {
+        parse_number(data, pos).ok_or_else(|| invalid_data("Invalid indent in magic rule"))?
+    };
+    if data.get(*pos) != Some(&b'>') {
+        return Err(invalid_data("Missing '>' in magic rule"));
+    }
+    *pos += 1;
+    let offset = parse_number(data, pos).ok_or_else(|| invalid_data("Invalid offset in magic rule"))?;
+    if data.get(*pos) != Some(&b'=') || *pos + 3 > data.len() {
+        return Err(invalid_data("Missing value in magic rule"));
+    }
+    let length = ((data[*pos + 1] as usize) << 8) | data[*pos + 2] as usize;
+    *pos += 3;
+    let mut value = data.get(*pos..*pos + length).ok_or_else(|| invalid_data("Truncated magic rule"))?.to_vec();
+    *pos += length;
+
+    let mut mask = None;
+    let mut word_size = 1;
+    let mut range = 1;
+    while *pos < data.len() && data[*pos] != b'\n' {
+        match data[*pos] {
+            b'&' => {
+                *pos += 1;
+                mask = Some(data.get(*pos..*pos + length).ok_or_else(|| invalid_data("Truncated magic mask"))?.to_vec());
+                *pos += length;
+            },
+            b'~' => {
+                *pos += 1;
+                word_size = parse_number(data, pos).ok_or_else(|| invalid_data("Invalid word size in magic rule"))?;
+            },
+            b'+' => {
+                *pos += 1;
+                range = parse_number(data, pos).ok_or_else(|| invalid_data("Invalid range in magic rule"))?;
+            },
+            // Unknown extensions are ignored up to the end of the line
+            _ => *pos += 1,
+        }
+    }
+    *pos += 1;
+
+    if word_size > 1 && cfg!(target_endian = "little") {
+        for chunk in value.chunks_mut(word_size) {
+            chunk.reverse();
+        }
+        if let Some(ref mut mask) = mask {
+            for chunk in mask.chunks_mut(word_size) {
+                chunk.reverse();
+            }
+        }
+    }
+
+    Ok((indent, MagicRule { offset, range, value, mask, children: Vec::new() }))
+}
+
+fn build_magic_tree(rules: &mut Vec<(usize, MagicRule)>, indent: usize) -> Vec<MagicRule> {
+    let mut tree = Vec::new();
+    while let Some(&(rule_indent, _)) = rules.last() {
+        if rule_indent < indent {
+            break;
+        }
+        let (_, mut rule) = rules.pop().unwrap();
+        rule.children = build_magic_tree(rules, indent + 1);
+        tree.push(rule);
+    }
+    tree
+}
+
+fn load_globs(path: &Path, globs: &mut Vec<Glob>) -> Result<(), io::Error> {
+    let file = BufReader::new(File::open(path)?);
+    for line in file.lines() {
+        let line = line?;
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split(':');
+        let weight = fields.next().and_then(|weight| weight.parse().ok());
+        let mimetype = fields.next();
+        let pattern = fields.next();
+        let flags = fields.next().unwrap_or("");
+        if let (Some(weight), Some(mimetype), Some(pattern)) = (weight, mimetype, pattern) {
+            globs.push(Glob {
+                weight,
+                mimetype: mimetype.to_string(),
+                pattern: pattern.to_string(),
+                case_sensitive: flags.split(',').any(|flag| flag == "cs"),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn load_magic(path: &Path, magic: &mut Magic) -> Result<(), io::Error> {
+    let mut data = Vec::new();
+    File::open(path)?.read_to_end(&mut data)?;
+    if !data.starts_with(b"MIME-Magic\0\n") {
+        return Err(invalid_data("Invalid magic file header"));
+    }
+
+    let mut pos = 12;
+    while pos < data.len() {
+        if data[pos] != b'[' {
+            return Err(invalid_data("Missing section header in magic file"));
+        }
+        let end = pos + data[pos..].iter().position(|&b| b == b'\n').ok_or_else(|| invalid_data("Truncated section header"))?;
+        let header = String::from_utf8_lossy(&data[pos + 1..end]).into_owned();
+        pos = end + 1;
+
+        let mut header_parts = header.trim_end_matches(']').splitn(2, ':');
+        let priority = header_parts.next().and_then(|priority| priority.parse().ok()).unwrap_or(50);
+        let mimetype = header_parts.next().unwrap_or("").to_string();
+
+        let mut rules = Vec::new();
+        while pos < data.len() && data[pos] != b'[' {
+            rules.push(parse_magic_rule(&data, &mut pos)?);
+        }
+        rules.reverse();
+        let rules = build_magic_tree(&mut rules, 0);
+
+        for rule in &rules {
+            magic.extent = magic.extent.max(rule.extent());
+        }
+        magic.sections.push(MagicSection { priority, mimetype, rules });
+    }
+    Ok(())
+}
+
+impl SharedMimeInfo {
+    pub fn load(mime_paths: &[PathBuf]) -> SharedMimeInfo {
+        let mut info = SharedMimeInfo {
+            paths: mime_paths.to_vec(),
+            ..Default::default()
+        };
+        for path in mime_paths {
+            let _ = info.load_aliases(&path.join("aliases"));
+            let _ = info.load_subclasses(&path.join("subclasses"));
+        }
+        info
+    }
+
+    fn globs(&self) -> &[Glob] {
+        self.globs.get_or_init(|| {
+            let mut globs = Vec::new();
+            for path in &self.paths {
+                let _ = load_globs(&path.join("globs2"), &mut globs);
+            }
+            globs
+        })
+    }
+
+    fn magic(&self) -> &Magic {
+        self.magic.get_or_init(|| {
+            let mut magic = Magic::default();
+            for path in &self.paths {
+                let _ = load_magic(&path.join("magic"), &mut magic);
+            }
+            magic
+        })
+    }
+
+    fn load_aliases(&mut self, path: &Path) -> Result<(), io::Error> {
+        let file = BufReader::new(File::open(path)?);
+        for line in file.lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            if let (Some(alias), Some(mimetype)) = (fields.next(), fields.next()) {
+                self.aliases.entry(alias.to_string()).or_insert_with(|| mimetype.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn load_subclasses(&mut self, path: &Path) -> Result<(), io::Error> {
+        let file = BufReader::new(File::open(path)?);
+        for line in file.lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+            if let (Some(mimetype), Some(parent)) = (fields.next(), fields.next()) {
+                let parents = self.subclasses.entry(mimetype.to_string()).or_default();
+                if !parents.iter().any(|known| known == parent) {
+                    parents.push(parent.to_string());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn unalias<'a>(&'a self, mimetype: &'a str) -> &'a str {
+        self.aliases.get(mimetype).map_or(mimetype, |mimetype| mimetype.as_str())
+    }
+
+    pub fn get_parents(&self, mimetype: &str) -> &[String] {
+        self.subclasses.get(mimetype).map_or(&[], |parents| parents.as_slice())
+    }
+
+    pub fn get_type_by_glob(&self, filename: &str) -> Option<&str> {
+        let basename = filename.rsplit('/').next().unwrap();
+        let mut best: Option<&Glob> = None;
+        for glob in self.globs() {
+            if !glob_match(&glob.pattern, basename, glob.case_sensitive) {
+                continue;
+            }
+            best = match best {
+                Some(best) if best.weight > glob.weight ||
+                    (best.weight == glob.weight && best.pattern.len() >= glob.pattern.len()) => Some(best),
+                _ => Some(glob),
+            };
+        }
+        best.map(|glob| glob.mimetype.as_str())
+    }
+
+    pub fn get_type_by_data(&self, data: &[u8]) -> Option<&str> {
+        let mut best: Option<&MagicSection> = None;
+        for section in &self.magic().sections {
+            if best.is_some_and(|best| best.priority >= section.priority) {
+                continue;
+            }
+            if section.rules.iter().any(|rule| rule.matches(data)) {
+                best = Some(section);
+            }
+        }
+        best.map(|section| section.mimetype.as_str())
+    }
+
+    pub fn get_type_by_content(&self, filename: &str) -> Result<Option<String>, io::Error> {
+        let magic = self.magic();
+        if magic.sections.is_empty() {
+            return Ok(None);
+        }
+        let mut data = Vec::new();
+        File::open(filename)?.take(magic.extent as u64).read_to_end(&mut data)?;
+        Ok(self.get_type_by_data(&data).map(|mimetype| mimetype.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_info() -> SharedMimeInfo {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mime");
+        SharedMimeInfo::load(&[path])
+    }
+
+    #[test]
+    fn test_sharedmime_globs() {
+        let info = test_info();
+        assert!(info.globs.get().is_none());
+        assert!(info.magic.get().is_none());
+
+        assert_eq!(info.get_type_by_glob("test.pdf"), Some("application/pdf"));
+        assert!(info.globs.get().is_some());
+        assert!(info.magic.get().is_none());
+        assert_eq!(info.get_type_by_glob("dir.pdf/TEST.PDF"), Some("application/pdf"));
+        assert_eq!(info.get_type_by_glob("archive.tar.gz"), Some("application/x-compressed-tar"));
+        assert_eq!(info.get_type_by_glob("archive.gz"), Some("application/gzip"));
+        assert_eq!(info.get_type_by_glob("Makefile"), Some("text/x-makefile"));
+        assert_eq!(info.get_type_by_glob("test.C"), Some("text/x-c++src"));
+        assert_eq!(info.get_type_by_glob("test.c"), Some("text/x-csrc"));
+        assert_eq!(info.get_type_by_glob("test.foo"), None);
+    }
+
+    #[test]
+    fn test_sharedmime_magic() {
+        let info = test_info();
+
+        assert_eq!(info.get_type_by_data(b"%PDF-1.4"), Some("application/pdf"));
+        assert_eq!(info.get_type_by_data(b"\n\n%PDF-1.4"), Some("application/pdf"));
+        assert_eq!(info.get_type_by_data(b"\x1f\x8b\x08"), Some("application/gzip"));
+        assert_eq!(info.get_type_by_data(b"<?xml version=\"1.0\"?><svg>"), Some("image/svg+xml"));
+        assert_eq!(info.get_type_by_data(b"<?xml version=\"1.0\"?><foo>"), Some("application/xml"));
+        assert_eq!(info.get_type_by_data(b"\x34\x12\x00\x00data"), Some("application/x-test-word"));
+        assert_eq!(info.get_type_by_data(b"\x34\x13\x00\x00data"), None);
+        assert_eq!(info.get_type_by_data(b"foo"), None);
+    }
+
+    #[test]
+    fn test_sharedmime_aliases_and_subclasses() {
+        let info = test_info();
+
+        assert_eq!(info.unalias("application/x-pdf"), "application/pdf");
+        assert_eq!(info.unalias("application/pdf"), "application/pdf");
+        assert_eq!(info.get_parents("application/xhtml+xml"), ["application/xml"]);
+        assert_eq!(info.get_parents("application/x-compressed-tar"), ["application/gzip"]);
+        assert!(info.get_parents("application/pdf").is_empty());
+    }
+
+    #[test]
+    fn test_sharedmime_content() {
+        let info = test_info();
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/compressed.txt.gz");
+
+        assert_eq!(info.get_type_by_content(path.to_str().unwrap()).unwrap(), Some(String::from("application/gzip")));
+    }
+
+    #[test]
+    fn test_sharedmime_nonexistant_dir() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/mime.");
+        let info = SharedMimeInfo::load(&[path]);
+
+        assert_eq!(info.get_type_by_glob("test.pdf"), None);
+        assert_eq!(info.get_type_by_content("Cargo.toml").unwrap(), None);
+    }
+}
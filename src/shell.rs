@@ -0,0 +1,317 @@
+// A command after placeholder substitution. Values are kept apart from the
+// command text, so they never get a meaning to the shell.
+#[derive(Debug)]
+#[derive(Clone)]
+#[derive(PartialEq)]
+pub enum Token {
+    Char(char),
+    Value(String),
+    // A value, that is put in quotes even when it needs none
+    QuotedValue(String),
+    // Multiple values, that only make sense as separate words
+    Values(Vec<String>),
+}
+
+// Words, that make the shell do more than running a single program
+const SHELL_WORDS: [&str; 34] = [
+    "!", "{", "}", "case", "do", "done", "elif", "else", "esac", "fi", "for", "if", "in", "then",
+    "until", "while", ".", ":", "alias", "break", "cd", "command", "continue", "eval", "exec",
+    "exit", "export", "readonly", "return", "set", "shift", "source", "trap", "unset",
+];
+
+#[derive(Clone, Copy)]
+#[derive(PartialEq)]
+enum Quoting {
+    Unquoted,
+    Single,
+    Double,
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t'
+}
+
+// Splits a command into words with the POSIX shell rules for quoting and
+// escaping. Returns None, if the command needs a shell to be run, e.g.
+// because it contains redirections, pipes, expansions or globs.
+pub fn split_words(tokens: &[Token]) -> Option<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut quoting = Quoting::Unquoted;
+    let mut tokens = tokens.iter().peekable();
+
+    while let Some(token) = tokens.next() {
+        let c = match *token {
+            Token::Char(c) => c,
+            Token::Value(ref value) | Token::QuotedValue(ref value) => {
+                word.push_str(value);
+                in_word = true;
+                continue;
+            },
+            Token::Values(ref values) => {
+                let standalone = match tokens.peek() {
+                    None => true,
+                    Some(&&Token::Char(c)) => is_blank(c),
+                    Some(_) => false,
+                };
+                if quoting != Quoting::Unquoted || in_word || !standalone {
+                    return None;
+                }
+                words.extend(values.iter().cloned());
+                continue;
+            },
+        };
+
+        match quoting {
+            Quoting::Unquoted => match c {
+                _ if is_blank(c) => {
+                    if in_word {
+                        words.push(std::mem::take(&mut word));
+                        in_word = false;
+                    }
+                },
+                '\\' => match tokens.next() {
+                    Some(&Token::Char('\n')) | None => return None,
+                    Some(&Token::Char(next)) => {
+                        word.push(next);
+                        in_word = true;
+                    },
+                    Some(_) => return None,
+                },
+                '\'' => {
+                    quoting = Quoting::Single;
+                    in_word = true;
+                },
+                '"' => {
+                    quoting = Quoting::Double;
+                    in_word = true;
+                },
+                '#' | '~' if !in_word => return None,
+                '=' if words.is_empty() => return None,
+                '|' | '&' | ';' | '<' | '>' | '(' | ')' | '$' | '`' | '*' | '?' | '[' | '\n' => return None,
+                _ => {
+                    word.push(c);
+                    in_word = true;
+                },
+            },
+            Quoting::Single => match c {
+                '\'' => quoting = Quoting::Unquoted,
+                _ => word.push(c),
+            },
+            Quoting::Double => match c {
+                '"' => quoting = Quoting::Unquoted,
+                '$' | '`' => return None,
+                '\\' => match tokens.peek() {
+                    Some(&&Token::Char(next)) if "$`\"\\".contains(next) => {
+                        word.push(next);
+                        tokens.next();
+                    },
+                    Some(&&Token::Char('\n')) => return None,
+                    _ => word.push('\\'),
+                },
+                _ => word.push(c),
+            },
+        }
+    }
+
+    if quoting != Quoting::Unquoted {
+        return None;
+    }
+    if in_word {
+        words.push(word);
+    }
+    if words.is_empty() || SHELL_WORDS.contains(&words[0].as_str()) {
+        return None;
+    }
+    Some(words)
+}
+
+fn is_safe_unquoted(c: char) -> bool {
+    c.is_alphanumeric() || "%+,-./:=@_".contains(c)
+}
+
+fn push_single_quoted(command: &mut String, value: &str) {
+    for c in value.chars() {
+        if c == '\'' {
+            command.push_str("'\\''");
+        } else {
+            command.push(c);
+        }
+    }
+}
+
+fn push_double_quoted(command: &mut String, value: &str) {
+    for c in value.chars() {
+        if "$`\"\\".contains(c) {
+            command.push('\\');
+        }
+        command.push(c);
+    }
+}
+
+fn push_escaped(command: &mut String, value: &str) {
+    if value.is_empty() {
+        command.push_str("''");
+    }
+    for c in value.chars() {
+        if c == '\n' {
+            // A backslash before a newline would remove it
+            command.push_str("'\n'");
+        } else {
+            if !is_safe_unquoted(c) {
+                command.push('\\');
+            }
+            command.push(c);
+        }
+    }
+}
+
+fn push_value(command: &mut String, value: &str, quoting: Quoting, always_quote: bool) {
+    match quoting {
+        Quoting::Unquoted if always_quote => {
+            command.push('\'');
+            push_single_quoted(command, value);
+            command.push('\'');
+        },
+        Quoting::Unquoted => push_escaped(command, value),
+        Quoting::Single => push_single_quoted(command, value),
+        Quoting::Double => push_double_quoted(command, value),
+    }
+}
+
+// Builds a command for the shell, where every value is quoted according to
+// the quotes around it, so the shell sees exactly the value
+pub fn join(tokens: &[Token]) -> String {
+    let mut command = String::new();
+    let mut quoting = Quoting::Unquoted;
+    let mut escaped = false;
+
+    for token in tokens {
+        match *token {
+            Token::Char(c) => {
+                command.push(c);
+                if escaped {
+                    escaped = false;
+                    continue;
+                }
+                match (quoting, c) {
+                    (Quoting::Unquoted, '\\') | (Quoting::Double, '\\') => escaped = true,
+                    (Quoting::Unquoted, '\'') => quoting = Quoting::Single,
+                    (Quoting::Unquoted, '"') => quoting = Quoting::Double,
+                    (Quoting::Single, '\'') | (Quoting::Double, '"') => quoting = Quoting::Unquoted,
+                    _ => {},
+                }
+            },
+            Token::Value(ref value) => {
+                escaped = false;
+                push_value(&mut command, value, quoting, false);
+            },
+            Token::QuotedValue(ref value) => {
+                escaped = false;
+                push_value(&mut command, value, quoting, true);
+            },
+            Token::Values(ref values) => {
+                escaped = false;
+                for (index, value) in values.iter().enumerate() {
+                    if index > 0 {
+                        command.push(' ');
+                    }
+                    push_value(&mut command, value, quoting, true);
+                }
+            },
+        }
+    }
+    command
+}
+
+pub fn split(command: &str) -> Option<Vec<String>> {
+    let tokens: Vec<Token> = command.chars().map(Token::Char).collect();
+    split_words(&tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(command: &str, value: &str) -> Vec<Token> {
+        let mut tokens = Vec::new();
+        let mut parts = command.split("%s");
+        tokens.extend(parts.next().unwrap().chars().map(Token::Char));
+        for part in parts {
+            tokens.push(Token::Value(value.to_string()));
+            tokens.extend(part.chars().map(Token::Char));
+        }
+        tokens
+    }
+
+    #[test]
+    fn test_split() {
+        assert_eq!(split("less"), Some(vec![String::from("less")]));
+        assert_eq!(split("  less  -R\t+G "), Some(vec![String::from("less"), String::from("-R"), String::from("+G")]));
+        assert_eq!(split("echo 'a b' \"c d\" e\\ f ''"), Some(vec![
+            String::from("echo"), String::from("a b"), String::from("c d"), String::from("e f"), String::new(),
+        ]));
+        assert_eq!(split("echo \"\\$a \\\" \\x\" '\\x'"), Some(vec![
+            String::from("echo"), String::from("$a \" \\x"), String::from("\\x"),
+        ]));
+        assert_eq!(split("echo a'b'\"c\""), Some(vec![String::from("echo"), String::from("abc")]));
+        assert_eq!(split("mpv --title=foo"), Some(vec![String::from("mpv"), String::from("--title=foo")]));
+    }
+
+    #[test]
+    fn test_split_needs_shell() {
+        for command in &[
+            "", "  ", "cat a | less", "cat a > b", "cat < a", "a && b", "a; b", "a &", "(a)",
+            "echo $HOME", "echo \"$HOME\"", "echo `date`", "echo \"`date`\"", "ls *.txt", "ls a?",
+            "ls [ab]", "ls ~/foo", "a # comment", "FOO=bar cmd", "echo 'foo", "echo \"foo",
+            "echo foo\\", "echo a\nb", "cd /tmp", "exec less", "if true", "! false", ". ./script",
+        ] {
+            assert_eq!(split(command), None, "{:?}", command);
+        }
+    }
+
+    #[test]
+    fn test_split_values() {
+        for value in &["test.txt", "a b.txt", "$(rm -rf ~).pdf", "`id`.pdf", "a'b\"c", "a\nb", "*", "-", ""] {
+            let expected = Some(vec![String::from("cat"), String::from("--"), value.to_string()]);
+            assert_eq!(split_words(&tokens("cat -- %s", value)), expected);
+            assert_eq!(split_words(&tokens("cat -- '%s'", value)), expected);
+            assert_eq!(split_words(&tokens("cat -- \"%s\"", value)), expected);
+        }
+        assert_eq!(split_words(&tokens("cat %s.bak", "a b")), Some(vec![String::from("cat"), String::from("a b.bak")]));
+        assert_eq!(split_words(&tokens("%s", "a b")), Some(vec![String::from("a b")]));
+        assert_eq!(split_words(&tokens("cat %s | less", "a")), None);
+    }
+
+    #[test]
+    fn test_split_multiple_values() {
+        let values = Token::Values(vec![String::from("text/plain"), String::from("a b.txt")]);
+        let mut tokens: Vec<Token> = "show ".chars().map(Token::Char).collect();
+        tokens.push(values.clone());
+        assert_eq!(split_words(&tokens), Some(vec![String::from("show"), String::from("text/plain"), String::from("a b.txt")]));
+
+        tokens.push(Token::Char('x'));
+        assert_eq!(split_words(&tokens), None);
+
+        let mut tokens: Vec<Token> = "show '".chars().map(Token::Char).collect();
+        tokens.push(values);
+        tokens.push(Token::Char('\''));
+        assert_eq!(split_words(&tokens), None);
+    }
+
+    #[test]
+    fn test_join() {
+        assert_eq!(join(&tokens("cat %s", "fo'o.txt")), "cat fo\\'o.txt");
+        assert_eq!(join(&tokens("cat '%s'", "fo'o.txt")), "cat 'fo'\\''o.txt'");
+        assert_eq!(join(&tokens("cat \"%s\"", "$a`b`\"c\\d'")), "cat \"\\$a\\`b\\`\\\"c\\\\d'\"");
+        assert_eq!(join(&tokens("cat %s", "a b\nc")), "cat a\\ b'\n'c");
+        assert_eq!(join(&tokens("cat %s", "")), "cat ''");
+        assert_eq!(join(&tokens("cat \"it's\" %s", "a'b")), "cat \"it's\" a\\'b");
+        assert_eq!(join(&tokens("cat \\' %s", "a'b")), "cat \\' a\\'b");
+        assert_eq!(join(&tokens("cat \"\\\"\" '%s'", "a'b")), "cat \"\\\"\" 'a'\\''b'");
+
+        let tokens = vec![Token::QuotedValue(String::from("it's")), Token::Char(' '), Token::Values(vec![String::from("a"), String::from("b c")])];
+        assert_eq!(join(&tokens), "'it'\\''s' 'a' 'b c'");
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::hash_map::RandomState;
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::hash::{BuildHasher, Hasher};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{symlink, DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::process;
+
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+}
+
+fn random_suffix() -> String {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    format!("{:016x}", hasher.finish())
+}
+
+impl TempDir {
+    pub fn new() -> Result<TempDir, io::Error> {
+        for _ in 0..16 {
+            let mut path = env::temp_dir();
+            path.push(format!("run-mailcap-rs.{}", random_suffix()));
+
+            match DirBuilder::new().mode(0o700).create(&path) {
+                Ok(()) => return Ok(TempDir { path }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::AlreadyExists, "Could not create temporary directory"))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // Leaves the directory in place, when it is dropped
+    pub fn keep(mut self) -> PathBuf {
+        std::mem::take(&mut self.path)
+    }
+
+    pub fn create_file(&self, name: &str) -> Result<(PathBuf, File), io::Error> {
+        let path = self.path.join(name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)?;
+        Ok((path, file))
+    }
+
+    pub fn create_fifo(&self, name: &str) -> Result<PathBuf, io::Error> {
+        let path = self.path.join(name);
+        let cpath = CString::new(path.as_os_str().as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        if unsafe { libc::mkfifo(cpath.as_ptr(), 0o600) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(path)
+    }
+
+    // Falls back to a copy, if a link is not possible and copy is set. A copy
+    // is only fine, when the file is not changed through it.
+    pub fn link_file(&self, name: &str, target: &Path, copy: bool) -> Result<PathBuf, io::Error> {
+        let path = self.path.join(name);
+        match fs::canonicalize(target).and_then(|target| symlink(target, &path)) {
+            Ok(()) => Ok(path),
+            Err(e) if !copy => Err(e),
+            Err(_e) => {
+                let (path, mut file) = self.create_file(name)?;
+                io::copy(&mut File::open(target)?, &mut file)?;
+                Ok(path)
+            },
+        }
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::prelude::*;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    use super::*;
+
+    #[test]
+    fn test_tempdir_removed_on_drop() {
+        let path;
+        {
+            let tempdir = TempDir::new().unwrap();
+            path = tempdir.path.clone();
+            assert!(path.is_dir());
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o700);
+
+            let (filepath, mut file) = tempdir.create_file("stdin").unwrap();
+            file.write_all(b"foo").unwrap();
+            assert_eq!(filepath.parent().unwrap(), path);
+            assert_eq!(fs::metadata(&filepath).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_tempdir_keep() {
+        let tempdir = TempDir::new().unwrap();
+        tempdir.create_file("stdin").unwrap();
+        let path = tempdir.keep();
+        assert!(path.join("stdin").exists());
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_tempdir_unique() {
+        let tempdir1 = TempDir::new().unwrap();
+        let tempdir2 = TempDir::new().unwrap();
+        assert_ne!(tempdir1.path, tempdir2.path);
+    }
+
+    #[test]
+    fn test_tempdir_link_file() {
+        let tempdir = TempDir::new().unwrap();
+        let (target, mut file) = tempdir.create_file("target").unwrap();
+        file.write_all(b"foo").unwrap();
+
+        let link = tempdir.link_file("link.txt", &target, false).unwrap();
+        assert_eq!(link.file_name().unwrap(), "link.txt");
+        assert_eq!(fs::read(&link).unwrap(), b"foo");
+    }
+
+    #[test]
+    fn test_tempdir_create_fifo() {
+        let tempdir = TempDir::new().unwrap();
+        let fifo = tempdir.create_fifo("done").unwrap();
+        assert!(fs::metadata(&fifo).unwrap().file_type().is_fifo());
+        assert_eq!(tempdir.create_fifo("done").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    }
+
+    #[test]
+    fn test_tempdir_link_file_missing_target() {
+        let tempdir = TempDir::new().unwrap();
+        let target = tempdir.path.join("nonexistant");
+        assert!(tempdir.link_file("link.txt", &target, false).is_err());
+        assert!(tempdir.link_file("link.txt", &target, true).is_err());
+    }
+
+    #[test]
+    fn test_tempdir_create_file_twice() {
+        let tempdir = TempDir::new().unwrap();
+        tempdir.create_file("stdin").unwrap();
+        assert_eq!(tempdir.create_file("stdin").unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+    }
+}
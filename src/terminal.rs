@@ -0,0 +1,370 @@
+use std::fs;
+use std::path::Path;
+use std::process;
+
+use super::shell::{self, Token};
+use super::tempfile::TempDir;
+
+// Where a command, that needs a terminal, can get one, when it was not
+// started from a terminal
+#[derive(Debug)]
+#[derive(Clone, Copy, Default)]
+#[derive(PartialEq)]
+pub enum Session {
+    #[default]
+    None,
+    X11,
+    Wayland,
+    Screen,
+    Tmux,
+}
+
+impl Session {
+    // A new window in a terminal multiplexer is closer to the user than a
+    // new terminal emulator, so the multiplexers come first
+    pub fn detect(tmux: bool, screen: bool, wayland: bool, x11: bool) -> Session {
+        if tmux {
+            Session::Tmux
+        } else if screen {
+            Session::Screen
+        } else if wayland {
+            Session::Wayland
+        } else if x11 {
+            Session::X11
+        } else {
+            Session::None
+        }
+    }
+}
+
+// In the templates, %t is replaced by the words of the terminal command, %T
+// by the title and %c by the command, that is run with "sh -c". The values
+// are quoted according to the quotes around the placeholders.
+const TERMINAL_TEMPLATE: &str = "%t -T '%T' -e sh -c '%c'";
+
+// Templates for terminals, that do not understand the arguments of xterm or
+// return before the command has finished without extra arguments
+const TERMINALS: [(&str, &str); 6] = [
+    ("alacritty", "%t --title '%T' -e sh -c '%c'"),
+    ("foot", "%t --title '%T' sh -c '%c'"),
+    ("gnome-terminal", "%t --wait --title '%T' -- sh -c '%c'"),
+    ("kitty", "%t --title '%T' sh -c '%c'"),
+    ("konsole", "%t --nofork -p tabtitle='%T' -e sh -c '%c'"),
+    ("wezterm", "%t start --always-new-process -- sh -c '%c'"),
+];
+
+// tmux and screen get a script, that runs the command and then signals its
+// end; %c in their templates is replaced by the script. tmux passes a single
+// argument to the default shell of the user, so the script runs "sh -c".
+const TMUX_TEMPLATE: &str = "tmux new-window -n '%T' '%c' \\; wait-for %s";
+const TMUX_SCRIPT: &str = "sh -c '%c'";
+const SCREEN_TEMPLATE: &str = "screen -X screen -t '%T' sh -c '%c' && cat %s";
+
+fn tokenize_template(template: &str, values: &[(char, &[String])]) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            tokens.push(Token::Char(c));
+            continue;
+        }
+        let value = chars.peek().and_then(|&next| values.iter().find(|&&(name, _)| name == next));
+        match (value, chars.peek()) {
+            (Some(&(_, words)), _) => {
+                chars.next();
+                for (index, word) in words.iter().enumerate() {
+                    if index > 0 {
+                        tokens.push(Token::Char(' '));
+                    }
+                    tokens.push(Token::Value(word.clone()));
+                }
+            },
+            (None, Some(&'%')) => {
+                chars.next();
+                tokens.push(Token::Char('%'));
+            },
+            (None, _) => tokens.push(Token::Char('%')),
+        }
+    }
+    tokens
+}
+
+#[derive(Debug)]
+pub struct Launch {
+    pub command: String,
+    pub pipeline: Option<Vec<Vec<String>>>,
+}
+
+fn launch_template(template: &str, terminal: &str, title: &str, command: &str, signal: &str, split: bool) -> Launch {
+    let title = [title.to_string()];
+    let command = [command.to_string()];
+    let signal = [signal.to_string()];
+    let mut values: Vec<(char, &[String])> = vec![('T', &title), ('c', &command), ('s', &signal)];
+
+    // A terminal command, that needs a shell itself, is put in as it is
+    let words = shell::split(terminal);
+    let mut template = template.to_string();
+    match words {
+        Some(ref words) => values.push(('t', words)),
+        None => template = template.replace("%t", &terminal.replace('%', "%%")),
+    }
+
+    let tokens = tokenize_template(&template, &values);
+    Launch {
+        command: shell::join(&tokens),
+        pipeline: if split && words.is_some() {
+            shell::split_words(&tokens).map(|argv| vec![argv])
+        } else {
+            None
+        },
+    }
+}
+
+// Reads templates for terminals from lines like "st %t -t '%T' -e sh -c '%c'"
+pub fn load_templates(paths: &[&Path]) -> Vec<(String, String)> {
+    let mut templates = Vec::new();
+    for path in paths {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(_e) => continue,
+        };
+        for line in content.lines() {
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            if let Some((name, template)) = line.split_once(char::is_whitespace) {
+                templates.push((name.to_string(), template.trim().to_string()));
+            }
+        }
+    }
+    templates
+}
+
+// Picks the template by the base name of the terminal program; the user
+// templates come first, xterm compatible arguments are the fallback
+pub fn find_template<'a>(templates: &'a [(String, String)], terminal: &str) -> &'a str {
+    let program = match shell::split(terminal) {
+        Some(words) => words[0].clone(),
+        None => terminal.split_whitespace().next().unwrap_or("").to_string(),
+    };
+    let name = program.rsplit('/').next().unwrap();
+    templates.iter()
+        .map(|(template_name, template)| (template_name.as_str(), template.as_str()))
+        .chain(TERMINALS.iter().cloned())
+        .find(|&(template_name, _)| template_name == name)
+        .map_or(TERMINAL_TEMPLATE, |(_, template)| template)
+}
+
+// Wraps the command, so that it is run in a new terminal (window) and the
+// launch only returns, when the command has finished. With split, the
+// launch is also given as a pipeline, if it can be run without a shell.
+pub fn launch(session: Session, terminal: &str, templates: &[(String, String)], command: &str, split: bool, tempdir: &mut Option<TempDir>) -> Option<Launch> {
+    match session {
+        Session::None => None,
+        Session::X11 | Session::Wayland => {
+            let template = find_template(templates, terminal);
+            Some(launch_template(template, terminal, command, command, "", split))
+        },
+        Session::Tmux => {
+            let signal = format!("run-mailcap-rs-{}", process::id());
+            let values = [signal_on_exit(command, &format!("tmux wait-for -S {}", signal))];
+            let script = shell::join(&tokenize_template(TMUX_SCRIPT, &[('c', &values)]));
+            Some(launch_template(TMUX_TEMPLATE, terminal, command, &script, &signal, split))
+        },
+        Session::Screen => {
+            // screen returns at once, so the end of the command is signaled
+            // through a fifo
+            if tempdir.is_none() {
+                *tempdir = Some(TempDir::new().ok()?);
+            }
+            let fifo = tempdir.as_ref().unwrap().create_fifo("done").ok()?;
+            Some(launch_screen(terminal, command, &fifo, split))
+        },
+    }
+}
+
+// Runs the signal command, when the command has finished, but also, when
+// the window is closed before, so the launch never waits forever; the
+// newline ends a trailing comment or "&" of the command
+fn signal_on_exit(command: &str, signal_command: &str) -> String {
+    let quoted = shell::join(&[Token::Value(signal_command.to_string())]);
+    format!("trap {} EXIT; trap exit HUP INT TERM; {}\nexit", quoted, command)
+}
+
+fn launch_screen(terminal: &str, command: &str, fifo: &Path, split: bool) -> Launch {
+    // The fifo is quoted for the trap, and once more with the trap and
+    // again with the script, when that is put into the template
+    let fifo = fifo.to_string_lossy();
+    let signal_command = format!(": > {}", shell::join(&[Token::Value(fifo.to_string())]));
+    let script = signal_on_exit(command, &signal_command);
+    launch_template(SCREEN_TEMPLATE, terminal, command, &script, &fifo, split)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::os::unix::process::CommandExt;
+    use std::process::{Command, Stdio};
+    use std::thread;
+    use std::time::Duration;
+    use libc;
+    use super::*;
+
+    #[test]
+    fn test_session_detect() {
+        assert_eq!(Session::detect(false, false, false, false), Session::None);
+        assert_eq!(Session::detect(false, false, false, true), Session::X11);
+        assert_eq!(Session::detect(false, false, true, true), Session::Wayland);
+        assert_eq!(Session::detect(false, true, true, true), Session::Screen);
+        assert_eq!(Session::detect(true, true, true, true), Session::Tmux);
+    }
+
+    fn strings(words: &[&str]) -> Vec<String> {
+        words.iter().map(|word| word.to_string()).collect()
+    }
+
+    #[test]
+    fn test_tokenize_template() {
+        let words = strings(&["a", "b c"]);
+        let value = strings(&["%c"]);
+        let tokens = tokenize_template("%t %c%%c %x", &[('t', &words), ('c', &value)]);
+        assert_eq!(shell::join(&tokens), "a b\\ c %c%c %x");
+    }
+
+    #[test]
+    fn test_load_templates() {
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/data/terminals");
+        let templates = load_templates(&[&path, Path::new("/nonexistent")]);
+        assert_eq!(templates, vec![
+            (String::from("st"), String::from("%t -t '%T' -e sh -c '%c'")),
+            (String::from("kitty"), String::from("%t --single-instance --wait-for-single-instance-window-close sh -c '%c'")),
+        ]);
+    }
+
+    #[test]
+    fn test_find_template() {
+        assert_eq!(find_template(&[], "xterm"), TERMINAL_TEMPLATE);
+        assert_eq!(find_template(&[], "urxvt -fn fixed"), TERMINAL_TEMPLATE);
+        assert_eq!(find_template(&[], "/usr/bin/alacritty"), "%t --title '%T' -e sh -c '%c'");
+        assert_eq!(find_template(&[], "'/opt/my apps/foot' --server"), "%t --title '%T' sh -c '%c'");
+        assert_eq!(find_template(&[], "wezterm"), "%t start --always-new-process -- sh -c '%c'");
+
+        let templates = vec![(String::from("wezterm"), String::from("%t -e sh -c '%c'"))];
+        assert_eq!(find_template(&templates, "wezterm"), "%t -e sh -c '%c'");
+        assert_eq!(find_template(&templates, "kitty"), "%t --title '%T' sh -c '%c'");
+
+        let launched = launch(Session::Wayland, "konsole", &templates, "vim a", false, &mut None).unwrap();
+        assert_eq!(launched.command, "konsole --nofork -p tabtitle='vim a' -e sh -c 'vim a'");
+    }
+
+    #[test]
+    fn test_launch() {
+        let mut tempdir = None;
+        assert!(launch(Session::None, "xterm", &[], "vim 'a'", false, &mut tempdir).is_none());
+
+        let launched = launch(Session::Wayland, "xterm", &[], "vim 'a'", false, &mut tempdir).unwrap();
+        assert_eq!(launched.command, "xterm -T 'vim '\\''a'\\''' -e sh -c 'vim '\\''a'\\'''");
+        assert!(launched.pipeline.is_none());
+
+        let launched = launch(Session::X11, "urxvt -fn fixed", &[], "cat a|less", true, &mut tempdir).unwrap();
+        assert_eq!(launched.command, "urxvt -fn fixed -T 'cat a|less' -e sh -c 'cat a|less'");
+        assert_eq!(launched.pipeline, Some(vec![strings(&["urxvt", "-fn", "fixed", "-T", "cat a|less", "-e", "sh", "-c", "cat a|less"])]));
+
+        let launched = launch(Session::X11, "kitty --single-instance", &[], "echo \"$HOME\" `id`", true, &mut tempdir).unwrap();
+        assert_eq!(launched.command, "kitty --single-instance --title 'echo \"$HOME\" `id`' sh -c 'echo \"$HOME\" `id`'");
+        assert_eq!(launched.pipeline.unwrap()[0][..2], strings(&["kitty", "--single-instance"])[..]);
+
+        let launched = launch(Session::X11, "$TERMINAL", &[], "vim a", true, &mut tempdir).unwrap();
+        assert_eq!(launched.command, "$TERMINAL -T 'vim a' -e sh -c 'vim a'");
+        assert!(launched.pipeline.is_none());
+
+        let launched = launch(Session::Tmux, "xterm", &[], "vim a", true, &mut tempdir).unwrap();
+        let signal = format!("run-mailcap-rs-{}", process::id());
+        let script = format!("trap tmux\\ wait-for\\ -S\\ {} EXIT; trap exit HUP INT TERM; vim a\nexit", signal);
+        assert_eq!(launched.command, format!("tmux new-window -n 'vim a' 'sh -c '\\''{}'\\''' \\; wait-for {}", script, signal));
+        assert_eq!(launched.pipeline, Some(vec![vec![
+            String::from("tmux"), String::from("new-window"), String::from("-n"), String::from("vim a"),
+            format!("sh -c '{}'", script), String::from(";"), String::from("wait-for"), signal,
+        ]]));
+
+        // Stands in for the default shell of the user, getting the script
+        let command = "echo \"$HOME\" 'a b'";
+        let launched = launch(Session::Tmux, "xterm", &[], command, true, &mut tempdir).unwrap();
+        let script = launched.pipeline.unwrap()[0][4].replacen("tmux\\ wait-for", ":\\ wait-for", 1);
+        let output = Command::new("sh").arg("-c").arg(&script).output().unwrap().stdout;
+        let expected = Command::new("sh").arg("-c").arg(command).output().unwrap().stdout;
+        assert_eq!(output, expected, "{}", script);
+        assert!(tempdir.is_none());
+
+        let launched = launch(Session::Screen, "xterm", &[], "vim a", true, &mut tempdir).unwrap();
+        assert!(launched.pipeline.is_none());
+        assert!(tempdir.is_some());
+    }
+
+    #[test]
+    fn test_launch_quoting() {
+        // Stands in for the terminal, running the command after -e
+        let terminal = "sh -c 'shift 3; exec \"$@\"' terminal";
+        let mut tempdir = None;
+        for command in &["echo \"$HOME\"", "echo '\"'\\'", "echo `echo x` $(echo y)", "printf '%s\\n' a\\ b"] {
+            let expected = Command::new("sh").arg("-c").arg(command).output().unwrap().stdout;
+            let launched = launch(Session::X11, terminal, &[], command, false, &mut tempdir).unwrap();
+            let output = Command::new("sh").arg("-c").arg(&launched.command).output().unwrap().stdout;
+            assert_eq!(output, expected, "{}", launched.command);
+        }
+    }
+
+    #[test]
+    fn test_launch_screen_waits_for_command() {
+        let mut tempdir = None;
+        let launched = launch(Session::Screen, "xterm", &[], "true", false, &mut tempdir).unwrap();
+
+        // Stands in for screen, running the command in the background
+        let command = launched.command.replacen("screen -X screen -t 'true' sh -c", "sh -c", 1)
+            .replacen(" && cat", " & cat", 1);
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    #[test]
+    fn test_launch_tmux_signals_when_killed() {
+        let launched = launch(Session::Tmux, "xterm", &[], "sleep 10", true, &mut None).unwrap();
+        let script = launched.pipeline.unwrap()[0][4].replacen("tmux\\ wait-for\\ -S", "echo", 1);
+
+        // Stands in for tmux, closing the window with the command
+        let child = Command::new("sh")
+            .arg("-c")
+            .arg(&script)
+            .stdout(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_millis(500));
+        unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGHUP) };
+        let output = child.wait_with_output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stdout), format!("run-mailcap-rs-{}\n", process::id()));
+    }
+
+    #[test]
+    fn test_launch_screen_fifo_with_spaces() {
+        let tempdir = TempDir::new().unwrap();
+        let fifo = tempdir.create_fifo("done 'now'").unwrap();
+        let launched = launch_screen("xterm", "true", &fifo, false);
+
+        // Stands in for screen, running the command in the background
+        let command = launched.command.replacen("screen -X screen -t 'true' sh -c", "sh -c", 1)
+            .replacen(" && cat", " & cat", 1);
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(&command)
+            .status()
+            .unwrap();
+        assert!(status.success(), "{}", command);
+    }
+}